@@ -0,0 +1,416 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use crate::cpu::{MAX_ROM_SIZE, PROGRAM_OFFSET};
+use crate::disasm;
+
+// How control leaves a basic block
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Edge {
+    // Into the next instruction
+    Fallthrough,
+    Jump,
+    // A skip instruction skipping over the next one
+    Skip,
+    // To a subroutine, the block continues with the return address
+    Call,
+}
+
+// Something in the ROM a static walk can't follow or that needs a closer look
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Finding {
+    // BNNN at this address, the target depends on V0
+    ComputedJump(u16),
+    // FX33 or FX55 at the first address writes over the code at the second
+    SelfModifying(u16, u16),
+    // A jump or call at the first address leaves the ROM for the second
+    OutsideRom(u16, u16),
+    // Not an instruction, yet reached by the walk
+    Invalid(u16),
+}
+
+pub(crate) struct Block {
+    pub(crate) start: u16,
+    // Address after the last instruction
+    pub(crate) end: u16,
+    pub(crate) successors: Vec<(u16, Edge)>,
+}
+
+// The result of walking a ROM from its entry point without running it
+pub(crate) struct Analysis {
+    rom: Vec<u8>,
+    // Addresses of every instruction reached
+    instructions: BTreeSet<u16>,
+    pub(crate) blocks: BTreeMap<u16, Block>,
+    // Subroutine entry points and what each of them calls. The program
+    // itself is the entry at 0x200.
+    pub(crate) calls: BTreeMap<u16, BTreeSet<u16>>,
+    pub(crate) findings: Vec<Finding>,
+}
+
+// Only the part of `rom` that fits in memory is walked
+pub(crate) fn analyze(rom: &[u8]) -> Analysis {
+    let mut analysis = Analysis {
+        rom: rom[..rom.len().min(MAX_ROM_SIZE)].to_vec(),
+        instructions: BTreeSet::new(),
+        blocks: BTreeMap::new(),
+        calls: BTreeMap::new(),
+        findings: Vec::new(),
+    };
+    let leaders = analysis.walk();
+    analysis.build_blocks(&leaders);
+    analysis.build_call_graph();
+    analysis.find_self_modification();
+    analysis
+}
+
+impl Analysis {
+    pub(crate) fn opcode(&self, address: u16) -> Option<u16> {
+        let offset = address.checked_sub(PROGRAM_OFFSET)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn in_rom(&self, address: u16) -> bool {
+        self.opcode(address).is_some()
+    }
+
+    // The program the analysis was made from
+    pub(crate) fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // Whether an instruction that can run starts at `address`
+    pub(crate) fn is_instruction(&self, address: u16) -> bool {
+        self.instructions.contains(&address)
+    }
+
+    // Every instruction that can run, in address order
+    pub(crate) fn opcodes(&self) -> impl Iterator<Item = u16> + '_ {
+        self.instructions.iter().filter_map(|address| self.opcode(*address))
+    }
+
+    // Whether the byte at `address` belongs to an instruction that can run
+    pub(crate) fn is_code(&self, address: u16) -> bool {
+        self.instructions.contains(&address) || address.checked_sub(1).is_some_and(|previous| self.instructions.contains(&previous))
+    }
+
+    // Finds every reachable instruction and returns where blocks start
+    fn walk(&mut self) -> BTreeSet<u16> {
+        let mut leaders = BTreeSet::from([PROGRAM_OFFSET]);
+        let mut pending = vec![PROGRAM_OFFSET];
+        self.calls.insert(PROGRAM_OFFSET, BTreeSet::new());
+
+        while let Some(address) = pending.pop() {
+            if self.instructions.contains(&address) {
+                continue;
+            }
+            let Some(opcode) = self.opcode(address) else {
+                continue;
+            };
+            if !decodes(opcode) {
+                self.findings.push(Finding::Invalid(address));
+                continue;
+            }
+            self.instructions.insert(address);
+
+            let successors = successors(address, opcode);
+            let ends_block = !matches!(successors.as_slice(), [(_, Edge::Fallthrough)]);
+            for (target, edge) in successors {
+                if !self.in_rom(target) {
+                    self.findings.push(Finding::OutsideRom(address, target));
+                    continue;
+                }
+                if ends_block {
+                    leaders.insert(target);
+                }
+                if edge == Edge::Call {
+                    self.calls.entry(target).or_default();
+                }
+                pending.push(target);
+            }
+            if opcode & 0xF000 == 0xB000 {
+                self.findings.push(Finding::ComputedJump(address));
+            }
+        }
+        leaders
+    }
+
+    fn build_blocks(&mut self, leaders: &BTreeSet<u16>) {
+        for &start in leaders.iter().filter(|leader| self.instructions.contains(leader)) {
+            let mut address = start;
+            loop {
+                let opcode = self.opcode(address).unwrap();
+                let successors = successors(address, opcode);
+                let next = address.wrapping_add(2);
+                let ends_block = !matches!(successors.as_slice(), [(_, Edge::Fallthrough)])
+                    || leaders.contains(&next)
+                    || !self.instructions.contains(&next);
+                if ends_block {
+                    // Targets outside the ROM or that aren't instructions
+                    // are findings, not blocks
+                    let successors = successors.into_iter().filter(|(target, _)| self.instructions.contains(target)).collect();
+                    self.blocks.insert(start, Block { start, end: next, successors });
+                    break;
+                }
+                address = next;
+            }
+        }
+    }
+
+    // What every subroutine calls, following its blocks but not its calls
+    fn build_call_graph(&mut self) {
+        let entries: Vec<u16> = self.calls.keys().copied().collect();
+        for entry in entries {
+            let mut seen = BTreeSet::new();
+            let mut pending = vec![entry];
+            let mut callees = BTreeSet::new();
+            while let Some(start) = pending.pop() {
+                if !seen.insert(start) {
+                    continue;
+                }
+                let Some(block) = self.blocks.get(&start) else {
+                    continue;
+                };
+                for &(target, edge) in &block.successors {
+                    if edge == Edge::Call {
+                        callees.insert(target);
+                    } else {
+                        pending.push(target);
+                    }
+                }
+            }
+            self.calls.insert(entry, callees);
+        }
+    }
+
+    // Follows I through each block to see where FX33 and FX55 store.
+    // Stores through an I the walk can't work out are left alone.
+    fn find_self_modification(&mut self) {
+        let mut findings = Vec::new();
+        for block in self.blocks.values() {
+            let mut index = None;
+            for address in (block.start..block.end).step_by(2) {
+                let opcode = self.opcode(address).unwrap();
+                let length = match (opcode >> 12, opcode & 0xFF) {
+                    (0xA, _) => {
+                        index = Some(opcode & 0xFFF);
+                        continue;
+                    },
+                    (0xF, 0x33) => 3,
+                    (0xF, 0x55) => ((opcode >> 8) & 0xF) + 1,
+                    // Anything else that moves I
+                    (0xF, 0x1E | 0x29 | 0x30 | 0x65) => {
+                        index = None;
+                        continue;
+                    },
+                    _ => continue,
+                };
+                if let Some(target) = index {
+                    if let Some(code) = (target..target + length).find(|byte| self.is_code(*byte)) {
+                        findings.push(Finding::SelfModifying(address, code));
+                    }
+                }
+                // FX55 moves I on some platforms, don't guess which
+                if length != 3 {
+                    index = None;
+                }
+            }
+        }
+        self.findings.extend(findings);
+    }
+
+    // One line per finding, for printing
+    pub(crate) fn describe_findings(&self) -> Vec<String> {
+        self.findings
+            .iter()
+            .map(|finding| match finding {
+                Finding::ComputedJump(at) => format!("{:#05X}: computed jump, targets unknown", at),
+                Finding::SelfModifying(at, code) => format!("{:#05X}: writes over code at {:#05X}", at, code),
+                Finding::OutsideRom(at, target) => format!("{:#05X}: goes to {:#05X} outside the ROM", at, target),
+                Finding::Invalid(at) => format!("{:#05X}: reached data that isn't an instruction", at),
+            })
+            .collect()
+    }
+
+    // The ROM with reachable code disassembled and everything else shown
+    // as data bytes with their sprite pixels
+    pub(crate) fn listing(&self) -> String {
+        let mut text = String::new();
+        let end = PROGRAM_OFFSET as usize + self.rom.len();
+        let mut address = PROGRAM_OFFSET;
+        while (address as usize) < end {
+            if address == PROGRAM_OFFSET {
+                let _ = writeln!(text, "; program start");
+            } else if self.calls.contains_key(&address) {
+                let _ = writeln!(text, "\n; subroutine {:#05X}", address);
+            } else if self.blocks.contains_key(&address) {
+                let _ = writeln!(text);
+            }
+            if self.instructions.contains(&address) {
+                let opcode = self.opcode(address).unwrap();
+                let _ = writeln!(text, "{:#05X}  {:04X}  {}", address, opcode, disasm::disassemble(opcode));
+                address += 2;
+            } else {
+                let byte = self.rom[(address - PROGRAM_OFFSET) as usize];
+                let _ = writeln!(text, "{:#05X}  {:02X}    DB {:#04X}  {}", address, byte, byte, disasm::sprite_row(byte));
+                address += 1;
+            }
+        }
+        text
+    }
+
+    // The control flow graph in Graphviz format, one cluster per subroutine.
+    // Calls are dashed, blocks with a finding are filled.
+    pub(crate) fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph rom {\n  node [shape=box fontname=\"monospace\"];\n");
+
+        let flagged: BTreeSet<u16> = self.findings
+            .iter()
+            .filter_map(|finding| match finding {
+                Finding::ComputedJump(at) | Finding::SelfModifying(at, _) | Finding::OutsideRom(at, _) => Some(*at),
+                Finding::Invalid(_) => None,
+            })
+            .collect();
+
+        // Blocks shared by several subroutines go with the first one
+        let mut placed = BTreeSet::new();
+        for &entry in self.calls.keys() {
+            let _ = writeln!(dot, "  subgraph cluster_{:03x} {{\n    label=\"{:#05X}\";", entry, entry);
+            let mut pending = vec![entry];
+            while let Some(start) = pending.pop() {
+                let Some(block) = self.blocks.get(&start) else {
+                    continue;
+                };
+                if !placed.insert(start) {
+                    continue;
+                }
+                let mut label = String::new();
+                for address in (block.start..block.end).step_by(2) {
+                    let opcode = self.opcode(address).unwrap();
+                    let _ = write!(label, "{:#05X}  {}\\l", address, disasm::disassemble(opcode));
+                }
+                let style = if (block.start..block.end).any(|address| flagged.contains(&address)) {
+                    " style=filled fillcolor=orange"
+                } else {
+                    ""
+                };
+                let _ = writeln!(dot, "    b{:03x} [label=\"{}\"{}];", start, label, style);
+                pending.extend(block.successors.iter().filter(|(_, edge)| *edge != Edge::Call).map(|(target, _)| *target));
+            }
+            let _ = writeln!(dot, "  }}");
+        }
+
+        for block in self.blocks.values() {
+            for (target, edge) in &block.successors {
+                let style = match edge {
+                    Edge::Fallthrough => "",
+                    Edge::Jump => " [label=\"jump\"]",
+                    Edge::Skip => " [label=\"skip\"]",
+                    Edge::Call => " [style=dashed label=\"call\"]",
+                };
+                let _ = writeln!(dot, "  b{:03x} -> b{:03x}{};", block.start, target, style);
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// Where execution can go after the instruction at `address`. Empty where
+// the walk can't follow, computed jumps included.
+fn successors(address: u16, opcode: u16) -> Vec<(u16, Edge)> {
+    let next = address.wrapping_add(2);
+    match (opcode >> 12, opcode & 0xFFF) {
+        (0x0, 0x0EE | 0x0FD) | (0xB, _) => Vec::new(),
+        (0x1, nnn) => vec![(nnn, Edge::Jump)],
+        (0x2, nnn) => vec![(nnn, Edge::Call), (next, Edge::Fallthrough)],
+        (0x3 | 0x4 | 0x5 | 0x9, _) | (0xE, _) => {
+            vec![(next, Edge::Fallthrough), (next.wrapping_add(2), Edge::Skip)]
+        },
+        _ => vec![(next, Edge::Fallthrough)],
+    }
+}
+
+// Whether `opcode` is an instruction, CHIP-8 or SUPER-CHIP
+fn decodes(opcode: u16) -> bool {
+    let n = opcode & 0xF;
+    match opcode >> 12 {
+        0x5 | 0x9 => n == 0,
+        0x8 => matches!(n, 0x0..=0x7 | 0xE),
+        0xE => matches!(opcode & 0xFF, 0x9E | 0xA1),
+        0xF => matches!(opcode & 0xFF, 0x07 | 0x0A | 0x15 | 0x18 | 0x1E | 0x29 | 0x30 | 0x33 | 0x55 | 0x65 | 0x75 | 0x85),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(opcodes: &[u16]) -> Vec<u8> {
+        opcodes.iter().flat_map(|opcode| opcode.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn jumps_skip_over_data() {
+        let analysis = analyze(&rom(&[0x1206, 0xFFFF, 0xFFFF, 0x1206]));
+        assert!(analysis.is_instruction(0x200));
+        assert!(analysis.is_instruction(0x206));
+        assert!(!analysis.is_instruction(0x202));
+        assert!(!analysis.is_code(0x204));
+        assert_eq!(analysis.blocks.keys().copied().collect::<Vec<_>>(), [0x200, 0x206]);
+        assert_eq!(analysis.blocks[&0x200].successors, [(0x206, Edge::Jump)]);
+        assert!(analysis.findings.is_empty());
+    }
+
+    #[test]
+    fn calls_continue_after_the_return_address() {
+        let analysis = analyze(&rom(&[0x2206, 0x1202, 0xFFFF, 0x00EE]));
+        assert_eq!(analysis.blocks[&0x200].successors, [(0x206, Edge::Call), (0x202, Edge::Fallthrough)]);
+        assert!(analysis.blocks[&0x206].successors.is_empty());
+        assert_eq!(analysis.calls[&0x200], BTreeSet::from([0x206]));
+        assert!(analysis.calls[&0x206].is_empty());
+        assert!(!analysis.is_instruction(0x204));
+    }
+
+    #[test]
+    fn skips_have_two_successors() {
+        let analysis = analyze(&rom(&[0x3000, 0x1200, 0x1204]));
+        assert_eq!(analysis.blocks[&0x200].successors, [(0x202, Edge::Fallthrough), (0x204, Edge::Skip)]);
+        assert!(analysis.blocks.contains_key(&0x202));
+        assert!(analysis.blocks.contains_key(&0x204));
+    }
+
+    #[test]
+    fn computed_jumps_end_the_walk() {
+        let analysis = analyze(&rom(&[0xB300, 0x1234]));
+        assert_eq!(analysis.findings, [Finding::ComputedJump(0x200)]);
+        assert!(analysis.blocks[&0x200].successors.is_empty());
+        assert!(!analysis.is_instruction(0x202));
+    }
+
+    #[test]
+    fn stores_over_code_are_found() {
+        let analysis = analyze(&rom(&[0xA200, 0xF155, 0x1204]));
+        assert_eq!(analysis.findings, [Finding::SelfModifying(0x202, 0x200)]);
+
+        // I moved by FX1E could point anywhere
+        let analysis = analyze(&rom(&[0xA200, 0xF01E, 0xF155, 0x1206]));
+        assert!(analysis.findings.is_empty());
+    }
+
+    #[test]
+    fn invalid_targets_get_no_edges() {
+        let analysis = analyze(&rom(&[0x1202, 0xFFFF]));
+        assert_eq!(analysis.findings, [Finding::Invalid(0x202)]);
+        assert!(analysis.blocks[&0x200].successors.is_empty());
+        assert!(!analysis.to_dot().contains("b202"));
+    }
+
+    #[test]
+    fn oversized_roms_stay_in_memory() {
+        let analysis = analyze(&vec![0; 0x10000]);
+        assert_eq!(analysis.rom().len(), MAX_ROM_SIZE);
+        assert!(analysis.is_instruction(0xFFE));
+    }
+}
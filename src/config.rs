@@ -0,0 +1,424 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use crate::cpu::{DisplayWait, KeyWait, TimingMode};
+use crate::display::Stretch;
+use crate::filter::FilterMode;
+use crate::keypad::Binding;
+use crate::recorder::RecordFormat;
+use crate::palette::Palette;
+use crate::terminal::Glyphs;
+use crate::timing::CatchUp;
+
+const USAGE: &str = "Usage: cargo run [path/to/game|directory] [chiptype] [--timing fixed|vip] [--catch-up skip|warp] [--display-wait none|before|after] \
+    [--key-wait press|release] [--stretch integer|aspect] [--scale n] [--config path] \
+    [--palette name|#rrggbb,#rrggbb[,#rrggbb,#rrggbb]] \
+    [--filter none|fade[:frames]|blend|buffered] [--frontend sdl|terminal|headless] [--glyphs blocks|braille] \
+    [--frames n] [--until-key-wait] [--screenshot] [--screenshot-scale n] [--record path.gif|path.y4m] [--record-format gif|y4m] \
+    [--record-audio] [--key-binding scancode|keycode] [--watch] [--gdb port] \
+    [--profile] [--profile-json path] [--coverage path.txt] \
+    [--cfg path.dot] [--disassemble]";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Frontend {
+    Sdl,
+    // Text output for SSH sessions and CI logs, doesn't need a display
+    Terminal,
+    // No output at all, runs a fixed number of frames as fast as possible
+    Headless,
+}
+
+#[derive(Clone)]
+pub(crate) struct Config {
+    // Empty until a ROM is picked when starting in the launcher
+    pub(crate) rom_path: String,
+    // Detected from the ROM when not given
+    pub(crate) chip_type: Option<String>,
+    // Show the ROM launcher for this directory first
+    pub(crate) browse_dir: Option<PathBuf>,
+    pub(crate) timing_mode: TimingMode,
+    // What happens after falling behind the wall clock, like after a suspend
+    pub(crate) catch_up: CatchUp,
+    // Overrides the platform's default when set
+    pub(crate) display_wait: Option<DisplayWait>,
+    // Overrides the platform's FX0A behavior when set
+    pub(crate) key_wait: Option<KeyWait>,
+    pub(crate) stretch: Stretch,
+    // Window size as a multiple of the hires screen, overrides the saved geometry
+    pub(crate) scale: Option<u32>,
+    pub(crate) config_path: Option<PathBuf>,
+    pub(crate) palette: Option<Palette>,
+    pub(crate) filter: FilterMode,
+    pub(crate) frontend: Frontend,
+    pub(crate) glyphs: Glyphs,
+    // Frames to run in headless mode, or in the terminal without a TTY
+    pub(crate) frames: u64,
+    // End a headless run early once the ROM waits for a key
+    pub(crate) until_key_wait: bool,
+    // Save a screenshot when a headless run finishes
+    pub(crate) screenshot: bool,
+    // Also used for recordings
+    pub(crate) screenshot_scale: u32,
+    // Start recording to this file right away
+    pub(crate) record_path: Option<PathBuf>,
+    // Format of recordings started with the hotkey
+    pub(crate) record_format: RecordFormat,
+    // Write the beeper to a .wav next to every recording
+    pub(crate) record_audio: bool,
+    // Overrides the key binding from the config file when set
+    pub(crate) key_binding: Option<Binding>,
+    // Reload and restart whenever the ROM file changes
+    pub(crate) watch: bool,
+    // Serve the GDB remote protocol on this port instead of running the ROM
+    pub(crate) gdb_port: Option<u16>,
+    // Print where the ROM spent its time on exit
+    pub(crate) profile: bool,
+    // Save the profile here instead of printing it
+    pub(crate) profile_json: Option<PathBuf>,
+    // Save the coverage map here on exit, with a heatmap next to it
+    pub(crate) coverage_path: Option<PathBuf>,
+    // Write the ROM's control flow graph here instead of running it
+    pub(crate) cfg_path: Option<PathBuf>,
+    // Print the ROM's code and data instead of running it
+    pub(crate) disassemble: bool,
+}
+
+impl Config {
+    pub(crate) fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut timing_mode = TimingMode::Fixed;
+        let mut catch_up = CatchUp::TimeWarp;
+        let mut display_wait = None;
+        let mut key_wait = None;
+        let mut stretch = Stretch::Aspect;
+        let mut scale = None;
+        let mut config_path = None;
+        let mut palette = None;
+        let mut filter = FilterMode::None;
+        let mut frontend = Frontend::Sdl;
+        let mut glyphs = Glyphs::HalfBlocks;
+        let mut frames = 600;
+        let mut until_key_wait = false;
+        let mut screenshot = false;
+        let mut screenshot_scale = 4;
+        let mut record_path = None;
+        let mut record_format = RecordFormat::Gif;
+        let mut record_audio = false;
+        let mut key_binding = None;
+        let mut watch = false;
+        let mut gdb_port = None;
+        let mut profile = false;
+        let mut profile_json = None;
+        let mut coverage_path = None;
+        let mut cfg_path = None;
+        let mut disassemble = false;
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--timing" => {
+                    timing_mode = match args.next().map(String::as_str) {
+                        Some("fixed") => TimingMode::Fixed,
+                        Some("vip") => TimingMode::CosmacVip,
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--catch-up" => {
+                    catch_up = match args.next().map(String::as_str) {
+                        Some("skip") => CatchUp::Skip,
+                        Some("warp") => CatchUp::TimeWarp,
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--display-wait" => {
+                    display_wait = match args.next().map(String::as_str) {
+                        Some("none") => Some(DisplayWait::None),
+                        Some("before") => Some(DisplayWait::BeforeDraw),
+                        Some("after") => Some(DisplayWait::AfterDraw),
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--key-wait" => {
+                    key_wait = match args.next().map(String::as_str) {
+                        Some("press") => Some(KeyWait::Press),
+                        Some("release") => Some(KeyWait::Release),
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--stretch" => {
+                    stretch = match args.next().map(String::as_str) {
+                        Some("integer") => Stretch::Integer,
+                        Some("aspect") => Stretch::Aspect,
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--scale" => {
+                    scale = match args.next().and_then(|value| value.parse().ok()) {
+                        Some(value) if value > 0 => Some(value),
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--config" => {
+                    config_path = match args.next() {
+                        Some(path) => Some(PathBuf::from(path)),
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--palette" => {
+                    palette = match args.next() {
+                        Some(spec) => Some(Palette::parse(spec)?),
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--filter" => {
+                    filter = match args.next().and_then(|spec| FilterMode::parse(spec)) {
+                        Some(filter) => filter,
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--frontend" => {
+                    frontend = match args.next().map(String::as_str) {
+                        Some("sdl") => Frontend::Sdl,
+                        Some("terminal") => Frontend::Terminal,
+                        Some("headless") => Frontend::Headless,
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--glyphs" => {
+                    glyphs = match args.next().map(String::as_str) {
+                        Some("blocks") => Glyphs::HalfBlocks,
+                        Some("braille") => Glyphs::Braille,
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--frames" => {
+                    frames = match args.next().and_then(|value| value.parse().ok()) {
+                        Some(value) => value,
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--until-key-wait" => until_key_wait = true,
+                "--screenshot" => screenshot = true,
+                "--screenshot-scale" => {
+                    screenshot_scale = match args.next().and_then(|value| value.parse().ok()) {
+                        Some(value) if value > 0 => value,
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--record" => {
+                    record_path = match args.next() {
+                        Some(path) => Some(PathBuf::from(path)),
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--record-format" => {
+                    record_format = match args.next().map(String::as_str) {
+                        Some("gif") => RecordFormat::Gif,
+                        Some("y4m") => RecordFormat::Y4m,
+                        _ => return Err(USAGE.to_string()),
+                    };
+                },
+                "--record-audio" => record_audio = true,
+                "--key-binding" => {
+                    key_binding = match args.next().and_then(|name| Binding::parse(name)) {
+                        Some(binding) => Some(binding),
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--watch" => watch = true,
+                "--gdb" => {
+                    gdb_port = match args.next().and_then(|value| value.parse().ok()) {
+                        Some(port) => Some(port),
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--profile" => profile = true,
+                "--profile-json" => {
+                    profile_json = match args.next() {
+                        Some(path) => Some(PathBuf::from(path)),
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--coverage" => {
+                    coverage_path = match args.next() {
+                        Some(path) => Some(PathBuf::from(path)),
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--cfg" => {
+                    cfg_path = match args.next() {
+                        Some(path) => Some(PathBuf::from(path)),
+                        None => return Err(USAGE.to_string()),
+                    };
+                },
+                "--disassemble" => disassemble = true,
+                option if option.starts_with("--") => return Err(USAGE.to_string()),
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        if positional.len() > 2 {
+            return Err(USAGE.to_string());
+        }
+        let chip_type = positional.get(1).cloned();
+        let (rom_path, browse_dir) = match positional.first() {
+            None => (String::new(), Some(PathBuf::from("."))),
+            Some(path) if Path::new(path).is_dir() => (String::new(), Some(PathBuf::from(path))),
+            Some(path) => (path.clone(), None),
+        };
+
+        Ok(Self {
+            rom_path,
+            chip_type,
+            browse_dir,
+            timing_mode,
+            catch_up,
+            display_wait,
+            key_wait,
+            stretch,
+            scale,
+            config_path,
+            palette,
+            filter,
+            frontend,
+            glyphs,
+            frames,
+            until_key_wait,
+            screenshot,
+            screenshot_scale,
+            record_path,
+            record_format,
+            record_audio,
+            key_binding,
+            watch,
+            gdb_port,
+            profile,
+            profile_json,
+            coverage_path,
+            cfg_path,
+            disassemble,
+        })
+    }
+
+    // The ROM file name without extension, used for per-ROM settings
+    pub(crate) fn rom_name(&self) -> String {
+        Path::new(&self.rom_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+// Settings that persist between runs, stored as `key = value` lines
+pub(crate) struct ConfigFile {
+    path: PathBuf,
+    // The file as it was read, so saving keeps comments and the order of
+    // hand-edited keys
+    lines: Vec<String>,
+    values: BTreeMap<String, String>,
+}
+
+impl ConfigFile {
+    // Loads the file at `path`, or the per-user default location. A missing
+    // or unreadable file just means there is nothing saved yet.
+    pub(crate) fn load(path: Option<PathBuf>) -> Self {
+        let path = path.unwrap_or_else(default_path);
+        let lines: Vec<String> = fs::read_to_string(&path)
+            .map(|contents| contents.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        let values = lines
+            .iter()
+            .filter_map(|line| entry(line))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Self { path, lines, values }
+    }
+
+    pub(crate) fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.values.get(key)?.parse().ok()
+    }
+
+    pub(crate) fn set<T: ToString>(&mut self, key: &str, value: T) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    // Rewrites only the lines whose value changed and adds new keys at the
+    // end. Nothing is written when nothing changed.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let mut present = BTreeSet::new();
+        let mut lines: Vec<String> = self.lines
+            .iter()
+            .map(|line| {
+                let Some((key, value)) = entry(line) else {
+                    return line.clone();
+                };
+                present.insert(key);
+                match self.values.get(key) {
+                    Some(new) if new != value => format!("{} = {}", key, new),
+                    _ => line.clone(),
+                }
+            })
+            .collect();
+        lines.extend(
+            self.values
+                .iter()
+                .filter(|(key, _)| !present.contains(key.as_str()))
+                .map(|(key, value)| format!("{} = {}", key, value)),
+        );
+        if lines == self.lines {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+        fs::write(&self.path, contents)
+    }
+}
+
+fn default_path() -> PathBuf {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_default();
+    base.join("chip8").join("chip8.cfg")
+}
+
+// The key and value on a `key = value` line, None for comments and blanks
+fn entry(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    let (key, value) = line.split_once('=')?;
+    Some((key.trim(), value.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saving_keeps_comments_and_order() {
+        let path = env::temp_dir().join(format!("chip8-config-test-{}.cfg", std::process::id()));
+        let original = "# keys for pong\npong.key.1 = w\n\nwindow_x = 10\npad.5 = dpup\n";
+        fs::write(&path, original).unwrap();
+
+        let mut config_file = ConfigFile::load(Some(path.clone()));
+        config_file.set("window_x", 10);
+        config_file.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+
+        config_file.set("window_x", 20);
+        config_file.set("window_y", 30);
+        config_file.save().unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved, "# keys for pong\npong.key.1 = w\n\nwindow_x = 20\npad.5 = dpup\nwindow_y = 30\n");
+    }
+}
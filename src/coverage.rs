@@ -0,0 +1,162 @@
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+use crate::analysis::Analysis;
+use crate::cpu::PROGRAM_OFFSET;
+use crate::disasm;
+
+// Heatmap layout: bytes per row and pixels per byte
+const HEATMAP_COLUMNS: usize = 64;
+const HEATMAP_CELL: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Access {
+    Executed,
+    // Sprite data for DXYN or registers loaded with FX65
+    Read,
+    // Stored by FX33 or FX55
+    Written,
+}
+
+// How every byte of memory was used while the ROM ran
+#[derive(Clone)]
+pub(crate) struct Coverage {
+    executed: Vec<u32>,
+    read: Vec<u32>,
+    written: Vec<u32>,
+    // First bytes of the instructions that were executed
+    instructions: Vec<bool>,
+}
+
+impl Coverage {
+    pub(crate) fn new() -> Self {
+        Self {
+            executed: vec![0; 4096],
+            read: vec![0; 4096],
+            written: vec![0; 4096],
+            instructions: vec![false; 4096],
+        }
+    }
+
+    pub(crate) fn instruction(&mut self, pc: u16) {
+        let pc = pc as usize & 0xFFF;
+        self.instructions[pc] = true;
+        self.mark(pc, 2, Access::Executed);
+    }
+
+    pub(crate) fn mark(&mut self, address: usize, length: usize, access: Access) {
+        let counts = match access {
+            Access::Executed => &mut self.executed,
+            Access::Read => &mut self.read,
+            Access::Written => &mut self.written,
+        };
+        let end = (address + length).min(counts.len());
+        for count in counts.iter_mut().take(end).skip(address) {
+            *count = count.saturating_add(1);
+        }
+    }
+
+    // "XRW" with a dash for every kind of access the bytes didn't see
+    fn flags(&self, address: usize, length: usize) -> String {
+        let used = |counts: &[u32]| counts[address..address + length].iter().any(|count| *count > 0);
+        [(&self.executed, 'X'), (&self.read, 'R'), (&self.written, 'W')]
+            .iter()
+            .map(|(counts, flag)| if used(counts) { *flag } else { '-' })
+            .collect()
+    }
+
+    // The ROM disassembled where it was executed or where the static walk
+    // found code, dumped as bytes elsewhere, every line marked with how it
+    // was used. Shows the program as loaded, not as it modified itself.
+    pub(crate) fn save_text(&self, path: &Path, rom: &[u8], analysis: &Analysis) -> io::Result<()> {
+        let rom_length = rom.len();
+        let start = PROGRAM_OFFSET as usize;
+        let end = (start + rom_length).min(self.executed.len());
+        let range = start..end;
+        let count = |counts: &[u32]| counts[range.clone()].iter().filter(|count| **count > 0).count();
+        let unused = range
+            .clone()
+            .filter(|address| self.executed[*address] + self.read[*address] + self.written[*address] == 0)
+            .count();
+        let percent = |bytes: usize| bytes as f64 * 100.0 / rom_length.max(1) as f64;
+
+        let mut text = String::new();
+        let _ = writeln!(text, "; {} ROM bytes", rom_length);
+        let _ = writeln!(text, "; executed {} ({:.1}%)", count(&self.executed), percent(count(&self.executed)));
+        let _ = writeln!(text, "; read as data {} ({:.1}%)", count(&self.read), percent(count(&self.read)));
+        let _ = writeln!(text, "; written {} ({:.1}%)", count(&self.written), percent(count(&self.written)));
+        let _ = writeln!(text, "; never used {} ({:.1}%)", unused, percent(unused));
+
+        let byte = |address: usize| rom[address - start];
+        let mut address = start;
+        while address < end {
+            let code = self.instructions[address] || analysis.is_instruction(address as u16);
+            if code && address + 1 < end {
+                let opcode = u16::from_be_bytes([byte(address), byte(address + 1)]);
+                let _ = writeln!(
+                    text,
+                    "{:#05X}  {:04X}  {}  {}",
+                    address,
+                    opcode,
+                    self.flags(address, 2),
+                    disasm::disassemble(opcode),
+                );
+                address += 2;
+            } else {
+                // Data is shown as sprite pixels too
+                let byte = byte(address);
+                let pixels = disasm::sprite_row(byte);
+                let _ = writeln!(text, "{:#05X}  {:02X}    {}  DB {:#04X}  {}", address, byte, self.flags(address, 1), byte, pixels);
+                address += 1;
+            }
+        }
+        fs::write(path, text)
+    }
+
+    // One square per ROM byte, 64 to a row. Green is executed, blue read
+    // and red written, brighter the more often.
+    pub(crate) fn save_png(&self, path: &Path, rom_length: usize) -> io::Result<()> {
+        let rows = rom_length.div_ceil(HEATMAP_COLUMNS).max(1);
+        let width = HEATMAP_COLUMNS * HEATMAP_CELL;
+        let height = rows * HEATMAP_CELL;
+
+        let max = |counts: &[u32]| counts.iter().copied().max().unwrap_or(0);
+        let maxima = [max(&self.written), max(&self.executed), max(&self.read)];
+        // Log scale, anything used at all is clearly visible
+        let level = |count: u32, max: u32| {
+            if count == 0 {
+                0
+            } else {
+                (64.0 + 191.0 * (count as f64).ln_1p() / (max as f64).ln_1p()) as u8
+            }
+        };
+
+        let mut data = vec![0; width * height * 3];
+        for offset in 0..rom_length {
+            let address = PROGRAM_OFFSET as usize + offset;
+            if address >= self.executed.len() {
+                break;
+            }
+            let counts = [self.written[address], self.executed[address], self.read[address]];
+            let color: Vec<u8> = counts.iter().zip(maxima).map(|(count, max)| level(*count, max)).collect();
+
+            let left = offset % HEATMAP_COLUMNS * HEATMAP_CELL;
+            let top = offset / HEATMAP_COLUMNS * HEATMAP_CELL;
+            for y in top..top + HEATMAP_CELL {
+                for x in left..left + HEATMAP_CELL {
+                    let pixel = (y * width + x) * 3;
+                    data[pixel..pixel + 3].copy_from_slice(&color);
+                }
+            }
+        }
+
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&data).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)
+    }
+}
@@ -1,13 +1,17 @@
+use std::collections::BTreeSet;
 use rand::random;
-const WIDTH: usize = 128;
-const HEIGHT: usize = 64;
+use crate::coverage::{Access, Coverage};
+use crate::profiler::Profiler;
+use crate::vip;
+pub(crate) const WIDTH: usize = 128;
+pub(crate) const HEIGHT: usize = 64;
 
-const LOWRES_WIDTH: usize = 64;
-const LOWRES_HEIGHT: usize = 32;
+pub(crate) const LOWRES_WIDTH: usize = 64;
+pub(crate) const LOWRES_HEIGHT: usize = 32;
 
 
 static SP_OFFSET: u16 = 0;
-static PROGRAM_OFFSET: u16 = 0x200;
+pub(crate) const PROGRAM_OFFSET: u16 = 0x200;
 
 const SCALE: u32 = 15;
 const WINDOW_WIDTH: u32 = (WIDTH as u32) * SCALE;
@@ -15,6 +19,8 @@ const WINDOW_HEIGHT: u32 = (HEIGHT as u32) * SCALE;
 const TICKS_PER_FRAME: usize = 20;
 const STACK_SIZE: usize = 16;
 const NUM_KEYS: usize = 16;
+// Everything from the program start to the end of memory
+pub(crate) const MAX_ROM_SIZE: usize = 4096 - PROGRAM_OFFSET as usize;
 
 const FONTSET_SIZE: usize = 80;
 
@@ -37,6 +43,7 @@ const FONTSET: [u8; FONTSET_SIZE] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+#[derive(Clone)]
 pub(crate) struct Chip8 {
     registers: Registers,
     timers: Timers,
@@ -47,28 +54,115 @@ pub(crate) struct Chip8 {
     stack: [u16; STACK_SIZE],
     hires: bool,
     v_blank_wait: bool,
+    v_blank_ready: bool,
+    screen_dirty: bool,
+    cleared_screen: Option<Box<[[bool; WIDTH]; HEIGHT]>>,
+    // Whether 00E0 keeps the screen it clears, only the buffered filter
+    // wants it
+    keep_cleared_screens: bool,
+    machine_cycles: i64,
+    instruction_count: u64,
+    key_wait_state: KeyWaitState,
+    // The program as loaded, so a reset can undo self-modifying code
+    rom: Vec<u8>,
+    error: Option<String>,
+    // Addresses the debugger stops at before executing them
+    pub(crate) breakpoints: BTreeSet<u16>,
+    breakpoint_hit: bool,
+    resuming: bool,
+    // State of the generator behind CXNN, so replaying is deterministic
+    rng: u64,
+    // Inputs since the last `take_inputs`, None unless a history is recording
+    inputs: Option<Vec<(u64, Input)>>,
+    // Counts every instruction executed when set
+    pub(crate) profiler: Option<Box<Profiler>>,
+    // Tracks how every byte of memory is used when set
+    pub(crate) coverage: Option<Box<Coverage>>,
     pub(crate) quirks: Quirks,
+    pub(crate) timing_mode: TimingMode,
 }
 
+// Something from outside the CPU that changed the machine, which has to
+// happen again at the same point when instructions are replayed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Input {
+    Timer,
+    VBlank,
+    // A single step let a waiting DXYN draw
+    DrawReady,
+    Key(usize, bool),
+    Reset,
+    // Machine cycles handed to the CPU with VIP timing
+    Cycles(u64),
+    // The CPU idled at a display wait or breakpoint, giving up the cycles
+    // it had left
+    Idle,
+}
+
+// The machine at one point in time, see `Chip8::snapshot`
+#[derive(Clone)]
+pub(crate) struct Snapshot(Box<Chip8>);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum TimingMode {
+    // Every opcode takes one tick of the CPU clock
+    Fixed,
+    // Every opcode takes as many machine cycles as on the COSMAC VIP
+    CosmacVip,
+}
+
+// When DXYN synchronises with the display's vertical blank
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum DisplayWait {
+    None,
+    // Like the VIP interpreter: wait for the vblank interrupt, then draw
+    BeforeDraw,
+    // Draw straight away, then stall until the next vblank
+    AfterDraw,
+}
+
+// When FX0A finishes waiting for input
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum KeyWait {
+    // As soon as a key is held down
+    Press,
+    // Like the original interpreters: once the pressed key is let go again,
+    // so one press can't satisfy several FX0A in a row
+    Release,
+}
+
+// How far a running FX0A has got
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum KeyWaitState {
+    Idle,
+    Press,
+    Release(usize),
+}
+
+#[derive(Clone)]
 pub(crate) struct Quirks {
+    platform: &'static str,
     shift_quirks: bool,
     load_store_quirks: bool,
     clip_quirks: bool,
     jump_quirks: bool,
     logic_quirks: bool,
-    v_blank_quirks: bool,
+    pub(crate) display_wait: DisplayWait,
+    pub(crate) key_wait: KeyWait,
     max_size: u16
 }
 
 impl Quirks {
     fn new() -> Self {
         let quirks = Self {
+            platform: "chip8",
             shift_quirks: false,
             load_store_quirks: false,
             clip_quirks: true,
             jump_quirks: false,
             logic_quirks: true,
-            v_blank_quirks: true,
+            display_wait: DisplayWait::BeforeDraw,
+            key_wait: KeyWait::Release,
             max_size: 3232
         };
         quirks
@@ -76,46 +170,78 @@ impl Quirks {
     pub(crate) fn get_chip(&mut self, chip: &str) {
         match chip {
             "chip8" => {
+                self.platform = "chip8";
                 self.shift_quirks = false;
                 self.load_store_quirks = false;
                 self.clip_quirks = true;
                 self.jump_quirks = false;
                 self.logic_quirks = true;
-                self.v_blank_quirks = true;
+                self.display_wait = DisplayWait::BeforeDraw;
+                self.key_wait = KeyWait::Release;
                 self.max_size = 3232
             },
             "schip" => {
+                self.platform = "schip";
                 self.shift_quirks = true;
                 self.load_store_quirks = true;
                 self.clip_quirks = true;
                 self.jump_quirks = true;
                 self.logic_quirks = false;
-                self.v_blank_quirks = false;
+                self.display_wait = DisplayWait::None;
+                self.key_wait = KeyWait::Release;
                 self.max_size = 3583;
             },
             "xo" => {
+                self.platform = "xo";
                 self.shift_quirks = false;
                 self.load_store_quirks = false;
                 self.clip_quirks = false;
                 self.jump_quirks = false;
                 self.logic_quirks = false;
-                self.v_blank_quirks = false;
+                self.display_wait = DisplayWait::None;
+                self.key_wait = KeyWait::Release;
                 self.max_size = 65024;
             }
             _ => {
+                self.platform = "chip8";
                 self.shift_quirks = false;
                 self.load_store_quirks = false;
                 self.clip_quirks = true;
                 self.jump_quirks = false;
                 self.logic_quirks = true;
-                self.v_blank_quirks = true;
+                self.display_wait = DisplayWait::BeforeDraw;
+                self.key_wait = KeyWait::Release;
                 self.max_size = 3232;
             }
         }
     }
 
+    pub(crate) fn platform(&self) -> &'static str {
+        self.platform
+    }
+
+    // The quirks that are switched on, for showing to the user
+    pub(crate) fn describe(&self) -> String {
+        let flags = [
+            ("shift", self.shift_quirks),
+            ("load/store", self.load_store_quirks),
+            ("clip", self.clip_quirks),
+            ("jump", self.jump_quirks),
+            ("logic", self.logic_quirks),
+        ];
+        let mut enabled: Vec<String> = flags
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect();
+        enabled.push(format!("wait:{:?}", self.display_wait));
+        enabled.push(format!("key:{:?}", self.key_wait));
+        enabled.join(" ")
+    }
+
 }
 
+#[derive(Clone)]
 struct Registers {
     index: u16,
     sp: u16,
@@ -124,6 +250,7 @@ struct Registers {
     rpl: [u8; 16],
 }
 
+#[derive(Clone)]
 struct Timers {
     delay: u8,
     sound: u8,
@@ -148,6 +275,24 @@ impl Chip8 {
             hires: false,
             quirks: Quirks::new(),
             v_blank_wait: false,
+            v_blank_ready: false,
+            screen_dirty: true,
+            cleared_screen: None,
+            keep_cleared_screens: false,
+            machine_cycles: 0,
+            instruction_count: 0,
+            key_wait_state: KeyWaitState::Idle,
+            rom: Vec::new(),
+            error: None,
+            breakpoints: BTreeSet::new(),
+            breakpoint_hit: false,
+            resuming: false,
+            // Xorshift needs a seed that isn't zero
+            rng: random::<u64>() | 1,
+            inputs: None,
+            profiler: None,
+            coverage: None,
+            timing_mode: TimingMode::Fixed,
         };
         emu.memory[..FONTSET_SIZE].copy_from_slice(&FONTSET);
 
@@ -156,7 +301,85 @@ impl Chip8 {
     pub(crate) fn get_hires(&self) -> bool {
         self.hires
     }
-    fn reset(&mut self) {
+
+    // Total number of instructions executed since power on
+    pub(crate) fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    pub(crate) fn pc(&self) -> u16 {
+        self.registers.pc
+    }
+
+    pub(crate) fn index(&self) -> u16 {
+        self.registers.index
+    }
+
+    pub(crate) fn v(&self) -> &[u8; 16] {
+        &self.registers.v
+    }
+
+    // Return addresses on the stack, oldest first
+    pub(crate) fn stack(&self) -> &[u16] {
+        &self.stack[..self.registers.sp as usize]
+    }
+
+    pub(crate) fn delay_timer(&self) -> u8 {
+        self.timers.delay
+    }
+
+    pub(crate) fn sound_timer(&self) -> u8 {
+        self.timers.sound
+    }
+
+    // The program as loaded, before any self-modification
+    pub(crate) fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub(crate) fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // Setters for debuggers that edit the machine state
+
+    pub(crate) fn set_pc(&mut self, pc: u16) {
+        self.registers.pc = pc & 0xFFF;
+    }
+
+    pub(crate) fn set_index(&mut self, index: u16) {
+        self.registers.index = index;
+    }
+
+    pub(crate) fn set_v(&mut self, register: usize, value: u8) {
+        self.registers.v[register] = value;
+    }
+
+    pub(crate) fn set_delay_timer(&mut self, value: u8) {
+        self.timers.delay = value;
+    }
+
+    pub(crate) fn set_sound_timer(&mut self, value: u8) {
+        self.timers.sound = value;
+    }
+
+    pub(crate) fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    // Whether an FX0A is waiting for a key to be pressed or released
+    pub(crate) fn blocked_on_key(&self) -> bool {
+        self.key_wait_state != KeyWaitState::Idle
+    }
+
+    // Whether the beeper is on
+    pub(crate) fn sound_active(&self) -> bool {
+        self.timers.sound > 0
+    }
+    // Puts the machine back into its power-on state with the current ROM
+    // freshly loaded. Quirks and timing settings are kept.
+    pub(crate) fn reset(&mut self) {
+        self.record(Input::Reset);
         self.timers.delay = 0;
         self.timers.sound = 0;
         self.registers.index = 0;
@@ -166,45 +389,305 @@ impl Chip8 {
         self.registers.rpl = [0; 16];
         self.operand = 0;
         self.screen = [[false; WIDTH]; HEIGHT];
+        self.screen_dirty = true;
+        self.cleared_screen = None;
         self.stack = [0; STACK_SIZE];
+        self.memory = [0; 4096];
         self.memory[..FONTSET_SIZE].copy_from_slice(&FONTSET);
+        let start = PROGRAM_OFFSET as usize;
+        self.memory[start..start + self.rom.len()].copy_from_slice(&self.rom);
         self.hires = false;
         self.v_blank_wait = false;
+        self.v_blank_ready = false;
+        self.machine_cycles = 0;
+        self.key_wait_state = KeyWaitState::Idle;
     }
 
+    // A call with a full stack is reported and turns into a jump
     fn push(&mut self, val: u16) {
+        if self.registers.sp as usize >= STACK_SIZE {
+            self.report(format!("Stack overflow at {:#05X}", val.wrapping_sub(2)));
+            return;
+        }
         self.stack[self.registers.sp as usize] = val;
         self.registers.sp += 1;
     }
 
+    // A return with an empty stack is reported and carries on after it
     fn pop(&mut self) -> u16 {
+        if self.registers.sp == SP_OFFSET {
+            self.report(format!("Stack underflow at {:#05X}", self.registers.pc.wrapping_sub(2)));
+            return self.registers.pc;
+        }
         self.registers.sp -= 1;
         self.stack[self.registers.sp as usize]
     }
 
-    pub(crate) fn load_rom(&mut self, data: &[u8]) {
-        let start = PROGRAM_OFFSET as usize;
-        let end = (PROGRAM_OFFSET as usize) + data.len();
-        self.memory[start..end].copy_from_slice(data);
+    fn report(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    // Whether `length` bytes from I are in memory. An instruction that
+    // would go past the end is reported and does nothing.
+    fn index_fits(&mut self, length: usize) -> bool {
+        if self.registers.index as usize + length <= self.memory.len() {
+            return true;
+        }
+        let pc = self.registers.pc.wrapping_sub(2);
+        self.report(format!("I at {:#05X} runs past the end of memory at {:#05X}", self.registers.index, pc));
+        false
+    }
+
+    // The last problem the program ran into, like an unknown opcode
+    pub(crate) fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+
+    // Replaces the program and resets the machine to run it. A ROM that
+    // doesn't fit in memory is refused and the old one is kept.
+    pub(crate) fn load_rom(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() > MAX_ROM_SIZE {
+            return Err(format!("ROM is {} bytes, only {} fit in memory", data.len(), MAX_ROM_SIZE));
+        }
+        self.rom = data.to_vec();
+        // Keys held for the old game would stay down forever otherwise
+        self.keys = [false; NUM_KEYS];
+        // Addresses of the old game mean nothing for the new one
+        if let Some(profiler) = &mut self.profiler {
+            **profiler = Profiler::new();
+        }
+        if let Some(coverage) = &mut self.coverage {
+            **coverage = Coverage::new();
+        }
+        self.reset();
+        Ok(())
+    }
+
+    pub(crate) fn is_key_pressed(&self, index: usize) -> bool {
+        self.keys[index]
     }
 
     pub(crate) fn keypress(&mut self, index: usize, pressed: bool) {
+        self.record(Input::Key(index, pressed));
         self.keys[index] = pressed;
     }
 
+    // Starts or stops journaling inputs for `take_inputs`
+    pub(crate) fn record_inputs(&mut self, enabled: bool) {
+        self.inputs = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    // Inputs since the last call, each with the instruction count it
+    // happened at
+    pub(crate) fn take_inputs(&mut self) -> Vec<(u64, Input)> {
+        self.inputs.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn record(&mut self, input: Input) {
+        if let Some(inputs) = &mut self.inputs {
+            inputs.push((self.instruction_count, input));
+        }
+    }
+
+    // A copy of the machine state. Breakpoints, the input journal, the
+    // profiler, coverage and pending errors belong to the session and are
+    // left out.
+    pub(crate) fn snapshot(&mut self) -> Snapshot {
+        let inputs = self.inputs.take();
+        let profiler = self.profiler.take();
+        let coverage = self.coverage.take();
+        let mut chip = self.clone();
+        self.inputs = inputs;
+        self.profiler = profiler;
+        self.coverage = coverage;
+        chip.breakpoints.clear();
+        chip.error = None;
+        Snapshot(Box::new(chip))
+    }
+
+    pub(crate) fn restore(&mut self, snapshot: &Snapshot) {
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        let inputs = self.inputs.take();
+        let profiler = self.profiler.take();
+        let coverage = self.coverage.take();
+        *self = (*snapshot.0).clone();
+        self.breakpoints = breakpoints;
+        self.inputs = inputs;
+        self.profiler = profiler;
+        self.coverage = coverage;
+        self.screen_dirty = true;
+        self.cleared_screen = None;
+    }
+
+    // Applies an input again without journaling it
+    pub(crate) fn replay_input(&mut self, input: Input) {
+        match input {
+            Input::Timer => self.tick_timers(),
+            Input::VBlank => self.end_frame(),
+            Input::DrawReady => self.v_blank_ready = true,
+            Input::Key(index, pressed) => self.keys[index] = pressed,
+            Input::Cycles(cycles) => self.machine_cycles += cycles as i64,
+            Input::Idle => self.machine_cycles = 0,
+            // Histories start over after a reset, so there is none to replay
+            Input::Reset => {},
+        }
+    }
+
+    // Executes the next instruction the way `clock` or `run_machine_cycles`
+    // did when it was recorded, breakpoints and display waits are already
+    // settled
+    pub(crate) fn replay_instruction(&mut self) {
+        self.execute_next();
+    }
+
     pub(crate) fn clock(&mut self) {
-        if self.v_blank_wait && self.quirks.v_blank_quirks {
+        if self.v_blank_wait || self.stop_at_breakpoint() {
             return;
         }
+        if let Some((pc, operation, cycles)) = self.execute_next() {
+            self.trace(pc, operation, cycles);
+        }
+    }
+
+    // Fetches and executes the instruction at PC. With VIP timing its cost
+    // comes off the machine cycle budget. Returns the address, opcode and
+    // cost for tracing, or None when the instruction didn't complete.
+    fn execute_next(&mut self) -> Option<(u16, u16, u64)> {
+        let pc = self.registers.pc;
+        let resuming = self.resuming;
         let operation = self.fetch();
+        let vx = self.registers.v[((operation & 0x0F00) >> 8) as usize];
         self.execute(operation);
+
+        // A DXYN that went back to wait for the display hasn't run yet, it
+        // is paid for and counted once it draws, and a breakpoint on it
+        // doesn't stop it twice
+        if self.v_blank_wait && self.registers.pc == pc {
+            self.instruction_count -= 1;
+            self.resuming = resuming;
+            return None;
+        }
+
+        let cycles = match self.timing_mode {
+            TimingMode::Fixed => 1,
+            TimingMode::CosmacVip => {
+                let skipped = self.registers.pc == pc.wrapping_add(4);
+                let cycles = vip::instruction_cycles(operation, vx, skipped);
+                self.machine_cycles -= cycles as i64;
+                cycles as u64
+            },
+        };
+        Some((pc, operation, cycles))
+    }
+
+    // Executes exactly one instruction for the debugger. A single step never
+    // blocks on the display: a pending wait is treated as if the frame had
+    // ended, so a waiting DXYN draws instead of spinning.
+    pub(crate) fn step(&mut self) {
+        if self.v_blank_wait {
+            self.vblank();
+        }
+        if self.peek() & 0xF000 == 0xD000 {
+            self.record(Input::DrawReady);
+            self.v_blank_ready = true;
+        }
+        if let Some((pc, operation, cycles)) = self.execute_next() {
+            self.trace(pc, operation, cycles);
+        }
+    }
+
+    // The display has finished a frame, which releases anything waiting on it
+    pub(crate) fn vblank(&mut self) {
+        self.record(Input::VBlank);
+        self.end_frame();
+    }
+
+    fn end_frame(&mut self) {
+        self.v_blank_wait = false;
+        self.v_blank_ready = true;
+        if self.timing_mode == TimingMode::CosmacVip {
+            self.machine_cycles -= vip::VBLANK_INTERRUPT_CYCLES;
+        }
+    }
+
+    fn peek(&self) -> u16 {
+        let pc = self.registers.pc as usize;
+        self.memory.get(pc..pc + 2).map_or(0, |bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    // Runs as many instructions as fit in the given number of COSMAC VIP
+    // machine cycles. An instruction that doesn't fully fit is still executed
+    // and the overrun is paid back from the next call.
+    pub(crate) fn run_machine_cycles(&mut self, cycles: u64) {
+        self.record(Input::Cycles(cycles));
+        self.machine_cycles += cycles as i64;
+        while self.machine_cycles > 0 {
+            if self.v_blank_wait || self.stop_at_breakpoint() {
+                // The interpreter idles until the display interrupt fires
+                self.record(Input::Idle);
+                self.machine_cycles = 0;
+                return;
+            }
+            if let Some((pc, operation, cycles)) = self.execute_next() {
+                self.trace(pc, operation, cycles);
+            }
+        }
+    }
+
+    // Hands an executed instruction to the profiler and coverage map
+    fn trace(&mut self, pc: u16, operation: u16, cycles: u64) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, operation, cycles);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.instruction(pc);
+        }
     }
 
+    fn cover(&mut self, address: u16, length: usize, access: Access) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.mark(address as usize, length, access);
+        }
+    }
+
+    // Whether the instruction at PC has a breakpoint that hasn't been resumed
+    // from. Stays stopped until `resume` is called.
+    fn stop_at_breakpoint(&mut self) -> bool {
+        if self.resuming || !self.breakpoints.contains(&self.registers.pc) {
+            return false;
+        }
+        self.breakpoint_hit = true;
+        true
+    }
+
+    pub(crate) fn toggle_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.remove(&address) {
+            self.breakpoints.insert(address);
+        }
+    }
+
+    // Lets execution continue past the breakpoint it stopped at
+    pub(crate) fn resume(&mut self) {
+        self.resuming = true;
+    }
+
+    // Whether a breakpoint was reached since the last call
+    pub(crate) fn take_breakpoint_hit(&mut self) -> bool {
+        std::mem::take(&mut self.breakpoint_hit)
+    }
+
+    // Past the end of memory there is nothing to fetch. That is reported
+    // and the program stays where it is, running 0000 which does nothing.
     fn fetch(&mut self) -> u16 {
-        let _operation = 0;
-        let top_half = self.memory[self.registers.pc as usize] as u16;
-        let bottom_half = self.memory[(self.registers.pc + 1) as usize] as u16;
-        self.operand = (top_half << 8) | bottom_half;
+        self.instruction_count += 1;
+        self.resuming = false;
+        let pc = self.registers.pc as usize;
+        let Some(bytes) = self.memory.get(pc..pc + 2) else {
+            self.report(format!("Program ran past the end of memory at {:#05X}", pc));
+            self.operand = 0;
+            return self.operand;
+        };
+        self.operand = u16::from_be_bytes([bytes[0], bytes[1]]);
         self.registers.pc += 2;
 
         self.operand
@@ -302,9 +785,14 @@ impl Chip8 {
                 for i in 0..length {
                     self.screen[i] = [false;WIDTH];
                 }
+                self.screen_dirty = true;
             },
             (0, 0, 0xE, 0) => {
+                if self.keep_cleared_screens {
+                    self.cleared_screen = Some(Box::new(self.screen));
+                }
                 self.screen = [[false; WIDTH]; HEIGHT];
+                self.screen_dirty = true;
             },
             (0, 0, 0xE, 0xE) => {
                 let return_addr = self.pop();
@@ -324,6 +812,7 @@ impl Chip8 {
                         self.screen[i][j] = row[j-4];
                     }
                 }
+                self.screen_dirty = true;
             },
             (0,0,0xF,0xC) => {
                 let _width = if self.hires { WIDTH } else { LOWRES_WIDTH };
@@ -340,13 +829,16 @@ impl Chip8 {
                     }
 
                 }
+                self.screen_dirty = true;
 
             },
             (0,0,0xF,0xE) => {
                 self.hires = false;
+                self.screen_dirty = true;
             },
             (0,0,0xF,0xF) => {
                 self.hires = true;
+                self.screen_dirty = true;
             }
             (0,_,_,_) => return,
             (1, _, _, _) => {
@@ -502,9 +994,21 @@ impl Chip8 {
             (0xC,_,_,_) => {
                 let x = op2 as usize;
                 let nn = (operation & 0xFF) as u8;
-                self.registers.v[x] = random::<u8>() & nn;
+                self.registers.v[x] = self.random_byte() & nn;
             }
             (0xD, _, _, _) => {
+                if self.quirks.display_wait == DisplayWait::BeforeDraw && !self.v_blank_ready {
+                    // Come back to this instruction once the frame has ended
+                    self.registers.pc -= 2;
+                    self.v_blank_wait = true;
+                    return;
+                }
+                self.v_blank_ready = false;
+                let length = if self.hires && op4 == 0 { 32 } else { op4 as usize };
+                if !self.index_fits(length) {
+                    return;
+                }
+
                 let x_coord = self.registers.v[op2 as usize] as u16;
                 let y_coord = self.registers.v[op3 as usize] as u16;
 
@@ -515,17 +1019,21 @@ impl Chip8 {
 
                 if self.hires && rows ==0 {
                     flip = self.draw_extended(x_coord, y_coord);
+                    self.cover(self.registers.index, 32, Access::Read);
                 } else {
                     flip = self.draw_normal(x_coord,y_coord,rows);
+                    self.cover(self.registers.index, rows as usize, Access::Read);
                 }
 
+                self.screen_dirty = true;
+
                 if flip {
                     self.registers.v[0xF] = 1;
                 } else {
                     self.registers.v[0xF] = 0;
                 }
 
-                if self.quirks.v_blank_quirks {
+                if self.quirks.display_wait == DisplayWait::AfterDraw {
                     self.v_blank_wait = true;
                 }
 
@@ -552,17 +1060,27 @@ impl Chip8 {
             },
             (0xF,_,0,0xA) => {
                 let x = op2 as usize;
-                let mut key = false;
-                for i in 0..self.keys.len() {
-                    if self.keys[i] {
-                        self.registers.v[x] = i as u8;
-                        key = true;
-                        break;
-                    }
-                }
-
-                if !key {
-                    self.registers.pc -=2;
+                let pressed = self.keys.iter().position(|key| *key);
+                let done = match (self.key_wait_state, pressed) {
+                    (KeyWaitState::Release(key), _) if !self.keys[key] => Some(key),
+                    (KeyWaitState::Release(_), _) => None,
+                    (_, Some(key)) if self.quirks.key_wait == KeyWait::Press => Some(key),
+                    (_, Some(key)) => {
+                        self.key_wait_state = KeyWaitState::Release(key);
+                        None
+                    },
+                    (_, None) => {
+                        self.key_wait_state = KeyWaitState::Press;
+                        None
+                    },
+                };
+
+                match done {
+                    Some(key) => {
+                        self.registers.v[x] = key as u8;
+                        self.key_wait_state = KeyWaitState::Idle;
+                    },
+                    None => self.registers.pc -= 2,
                 }
             }
             (0xF,_,1,5) => {
@@ -575,7 +1093,7 @@ impl Chip8 {
             },
             (0xF,_,1,0xE) => {
                 let x = op2 as usize;
-                self.registers.index += self.registers.v[x] as u16;
+                self.registers.index = self.registers.index.wrapping_add(self.registers.v[x] as u16);
 
             },
             (0xF,_,2,9) => {
@@ -595,29 +1113,41 @@ impl Chip8 {
                 let hundreds = (v / 100.0).floor() as u8;
                 let tens = ((v / 10.0) % 10.0).floor() as u8;
                 let ones = (v % 10.0) as u8;
+                if !self.index_fits(3) {
+                    return;
+                }
 
                 self.memory[self.registers.index as usize] = hundreds;
                 self.memory[(self.registers.index + 1) as usize] = tens;
                 self.memory[(self.registers.index + 2) as usize] = ones;
+                self.cover(self.registers.index, 3, Access::Written);
             },
             (0xF, _, 5, 5) => {
                 let x = op2 as usize;
                 let i = self.registers.index as usize;
+                if !self.index_fits(x + 1) {
+                    return;
+                }
                 for index in 0..=x {
                     self.memory[i + index] = self.registers.v[index];
                 }
+                self.cover(i as u16, x + 1, Access::Written);
                 if !self.quirks.load_store_quirks {
-                    self.registers.index = self.registers.index + 1;
+                    self.registers.index = self.registers.index.wrapping_add(1);
                 }
             },
             (0xF, _, 6, 5) => {
                 let x = op2 as usize;
                 let i = self.registers.index as usize;
+                if !self.index_fits(x + 1) {
+                    return;
+                }
                 for index in 0..=x {
                     self.registers.v[index] = self.memory[i + index];
                 }
+                self.cover(i as u16, x + 1, Access::Read);
                 if !self.quirks.load_store_quirks {
-                    self.registers.index = self.registers.index + 1;
+                    self.registers.index = self.registers.index.wrapping_add(1);
                 }
             }
             (0xF, _,7,5) => {
@@ -634,12 +1164,19 @@ impl Chip8 {
                     self.registers.v[counter] = self.registers.rpl[counter];
                 }
             }
-            (_, _, _, _) => unimplemented!("Unimplemented opcode: {:#04x}", operation),
+            (_, _, _, _) => {
+                let pc = self.registers.pc.wrapping_sub(2);
+                self.report(format!("Unknown opcode {:04X} at {:#05X}", operation, pc));
+            },
         }
     }
 
     pub(crate) fn update_timer(&mut self) {
-        self.v_blank_wait = false;
+        self.record(Input::Timer);
+        self.tick_timers();
+    }
+
+    fn tick_timers(&mut self) {
         if self.timers.delay > 0 {
             self.timers.delay -= 1;
         }
@@ -650,7 +1187,197 @@ impl Chip8 {
         }
     }
 
+    // Xorshift64, the high bits are the better ones
+    fn random_byte(&mut self) -> u8 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 56) as u8
+    }
+
+    // Whether the screen changed since the last call, for frontends that
+    // only redraw when they have to
+    pub(crate) fn take_screen_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.screen_dirty, false)
+    }
+
+    // Makes 00E0 keep the screen it clears for `take_cleared_screen`
+    pub(crate) fn keep_cleared_screens(&mut self, enabled: bool) {
+        self.keep_cleared_screens = enabled;
+        if !enabled {
+            self.cleared_screen = None;
+        }
+    }
+
+    // The screen as it was right before the most recent 00E0, which for most
+    // games is the last completely drawn frame. Always None unless
+    // `keep_cleared_screens` was switched on.
+    pub(crate) fn take_cleared_screen(&mut self) -> Option<Box<[[bool; WIDTH]; HEIGHT]>> {
+        self.cleared_screen.take()
+    }
+
     pub(crate) fn get_screen_buf(&self) -> &[[bool; WIDTH];HEIGHT] {
         &self.screen
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chip(program: &[u16]) -> Chip8 {
+        let rom: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        let mut chip = Chip8::new();
+        chip.load_rom(&rom).unwrap();
+        chip
+    }
+
+    // FX0A into V3, then loop
+    const WAIT_FOR_KEY: [u16; 2] = [0xF30A, 0x1202];
+
+    #[test]
+    fn key_wait_finishes_on_release() {
+        let mut chip = chip(&WAIT_FOR_KEY);
+        chip.clock();
+        assert!(chip.blocked_on_key());
+        assert_eq!(chip.pc(), 0x200);
+
+        chip.keypress(0x5, true);
+        chip.clock();
+        chip.clock();
+        assert!(chip.blocked_on_key());
+        assert_eq!(chip.pc(), 0x200);
+
+        chip.keypress(0x5, false);
+        chip.clock();
+        assert!(!chip.blocked_on_key());
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(chip.v()[3], 0x5);
+    }
+
+    #[test]
+    fn key_held_when_the_wait_starts_counts_once_released() {
+        let mut chip = chip(&WAIT_FOR_KEY);
+        chip.keypress(0xA, true);
+        chip.clock();
+        assert!(chip.blocked_on_key());
+        chip.keypress(0xA, false);
+        chip.clock();
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(chip.v()[3], 0xA);
+    }
+
+    #[test]
+    fn second_key_during_the_wait_is_ignored() {
+        let mut chip = chip(&WAIT_FOR_KEY);
+        chip.clock();
+        chip.keypress(0x2, true);
+        chip.clock();
+        chip.keypress(0x7, true);
+        chip.clock();
+        // Letting go of the second key doesn't finish it either
+        chip.keypress(0x7, false);
+        chip.clock();
+        assert!(chip.blocked_on_key());
+
+        chip.keypress(0x2, false);
+        chip.clock();
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(chip.v()[3], 0x2);
+    }
+
+    #[test]
+    fn key_wait_on_press() {
+        let mut chip = chip(&WAIT_FOR_KEY);
+        chip.quirks.key_wait = KeyWait::Press;
+        chip.clock();
+        assert!(chip.blocked_on_key());
+        chip.keypress(0xC, true);
+        chip.clock();
+        assert!(!chip.blocked_on_key());
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(chip.v()[3], 0xC);
+    }
+
+    // Draws the 0 glyph at 0,0, then loops
+    const DRAW: [u16; 3] = [0xA000, 0xD015, 0x1204];
+
+    fn lit_pixels(chip: &Chip8) -> usize {
+        chip.get_screen_buf().iter().flatten().filter(|pixel| **pixel).count()
+    }
+
+    #[test]
+    fn draw_before_vblank_waits_for_it() {
+        let mut chip = chip(&DRAW);
+        chip.quirks.display_wait = DisplayWait::BeforeDraw;
+        for _ in 0..3 {
+            chip.clock();
+        }
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(lit_pixels(&chip), 0);
+        assert_eq!(chip.instruction_count(), 1);
+
+        chip.vblank();
+        chip.clock();
+        assert_eq!(chip.pc(), 0x204);
+        assert_eq!(lit_pixels(&chip), 14);
+        assert_eq!(chip.instruction_count(), 2);
+
+        // The sprite was drawn once, a second XOR would have erased it
+        for _ in 0..3 {
+            chip.vblank();
+            chip.clock();
+        }
+        assert_eq!(lit_pixels(&chip), 14);
+    }
+
+    #[test]
+    fn draw_after_vblank_stalls_until_it() {
+        let mut chip = chip(&DRAW);
+        chip.quirks.display_wait = DisplayWait::AfterDraw;
+        chip.clock();
+        chip.clock();
+        assert_eq!(chip.pc(), 0x204);
+        assert_eq!(lit_pixels(&chip), 14);
+
+        chip.clock();
+        assert_eq!(chip.pc(), 0x204);
+        chip.vblank();
+        chip.clock();
+        assert_eq!(chip.pc(), 0x204);
+        assert_eq!(chip.instruction_count(), 3);
+        assert_eq!(lit_pixels(&chip), 14);
+    }
+
+    #[test]
+    fn single_steps_never_wait_for_the_display() {
+        for display_wait in [DisplayWait::BeforeDraw, DisplayWait::AfterDraw] {
+            let mut chip = chip(&DRAW);
+            chip.quirks.display_wait = display_wait;
+            chip.step();
+            chip.step();
+            assert_eq!(chip.pc(), 0x204, "{:?}", display_wait);
+            assert_eq!(lit_pixels(&chip), 14, "{:?}", display_wait);
+            assert_eq!(chip.instruction_count(), 2, "{:?}", display_wait);
+
+            chip.step();
+            assert_eq!(chip.pc(), 0x204, "{:?}", display_wait);
+            assert_eq!(chip.instruction_count(), 3, "{:?}", display_wait);
+            assert_eq!(lit_pixels(&chip), 14, "{:?}", display_wait);
+        }
+    }
+
+    #[test]
+    fn waiting_draw_is_stepped_once() {
+        // A draw already waiting when the debugger steps runs right away
+        let mut chip = chip(&DRAW);
+        chip.quirks.display_wait = DisplayWait::BeforeDraw;
+        chip.clock();
+        chip.clock();
+        assert_eq!(chip.pc(), 0x202);
+        chip.step();
+        assert_eq!(chip.pc(), 0x204);
+        assert_eq!(lit_pixels(&chip), 14);
+        assert_eq!(chip.instruction_count(), 2);
+    }
+}
@@ -0,0 +1,299 @@
+use sdl3::event::Event;
+use sdl3::keyboard::{Keycode, Mod};
+use sdl3::pixels::Color;
+use sdl3::rect::Rect;
+use sdl3::render::Canvas;
+use sdl3::video::Window;
+use sdl3::VideoSubsystem;
+use crate::analysis::{self, Analysis};
+use crate::cpu::{Chip8, PROGRAM_OFFSET};
+use crate::disasm;
+use crate::font;
+use crate::history::History;
+use crate::text::draw_scaled;
+
+const WINDOW_WIDTH: u32 = 1000;
+const WINDOW_HEIGHT: u32 = 640;
+
+// Font pixel size, smaller than the overlay to fit more on screen
+const SCALE: u32 = 2;
+const CHAR_WIDTH: u32 = (font::GLYPH_WIDTH + 1) * SCALE;
+const LINE_HEIGHT: u32 = (font::GLYPH_HEIGHT + 3) * SCALE;
+
+// Left edges of the panes, in characters
+const REGISTERS_COLUMN: u32 = 1;
+const DISASSEMBLY_COLUMN: u32 = 28;
+const MEMORY_COLUMN: u32 = 60;
+
+const DISASSEMBLY_LINES: u16 = 32;
+const MEMORY_ROWS: u16 = 32;
+const BYTES_PER_ROW: u16 = 8;
+// Rows of the sprite preview, the most a DXYN can draw
+const SPRITE_ROWS: u16 = 16;
+const SPRITE_PIXEL: u32 = 8;
+
+const TEXT: Color = Color::RGBA(220, 220, 220, 255);
+const HEADING: Color = Color::RGBA(255, 255, 0, 255);
+const HIGHLIGHT: Color = Color::RGBA(255, 255, 0, 255);
+const BREAKPOINT: Color = Color::RGBA(255, 64, 64, 255);
+const BACKGROUND: Color = Color::RGBA(16, 16, 32, 255);
+
+// What the debugger asks the main loop to do
+pub(crate) enum DebugCommand {
+    Step,
+    Continue,
+    Pause,
+    StepBack,
+    ReverseContinue,
+}
+
+// A second window showing the machine state. Steering execution is left to
+// the main loop through `DebugCommand`, breakpoints go straight to the core.
+pub(crate) struct Debugger {
+    canvas: Canvas<Window>,
+    // First address of the memory pane
+    memory_start: u16,
+    // Address selected in the disassembly pane, PC when None
+    cursor: Option<u16>,
+    // Recorded while the debugger is open, for stepping backwards
+    pub(crate) history: History,
+    // Tells code from data in the disassembly pane, redone when the ROM changes
+    analysis: Analysis,
+}
+
+impl Debugger {
+    pub(crate) fn open(video_subsystem: &VideoSubsystem, chip: &mut Chip8) -> Result<Self, String> {
+        let window = video_subsystem
+            .window("Chip8 Debugger", WINDOW_WIDTH, WINDOW_HEIGHT)
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = sdl3::render::create_renderer(window, Some(c"software")).map_err(|e| e.to_string())?;
+        Ok(Self {
+            canvas,
+            memory_start: 0x200,
+            cursor: None,
+            history: History::new(chip),
+            analysis: analysis::analyze(chip.rom()),
+        })
+    }
+
+    pub(crate) fn close(self, chip: &mut Chip8) {
+        self.history.close(chip);
+    }
+
+    pub(crate) fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    // Handles an event sent to the debugger window
+    pub(crate) fn handle_event(&mut self, event: &Event, chip: &mut Chip8) -> Option<DebugCommand> {
+        let Event::KeyDown { keycode: Some(key), keymod, .. } = event else {
+            if let Event::MouseWheel { y, .. } = event {
+                self.scroll_memory(-(*y as i32) * 2);
+            }
+            return None;
+        };
+
+        let cursor = self.cursor.unwrap_or(chip.pc());
+        let reverse = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+        match *key {
+            Keycode::F10 | Keycode::S if reverse => {
+                self.cursor = None;
+                return Some(DebugCommand::StepBack);
+            },
+            Keycode::F5 | Keycode::C if reverse => {
+                self.cursor = None;
+                return Some(DebugCommand::ReverseContinue);
+            },
+            Keycode::F10 | Keycode::S => {
+                self.cursor = None;
+                return Some(DebugCommand::Step);
+            },
+            Keycode::F5 | Keycode::C => {
+                self.cursor = None;
+                return Some(DebugCommand::Continue);
+            },
+            Keycode::P => return Some(DebugCommand::Pause),
+            Keycode::B | Keycode::F9 => chip.toggle_breakpoint(cursor),
+            Keycode::Up => self.cursor = Some(cursor.wrapping_sub(2) & 0xFFF),
+            Keycode::Down => self.cursor = Some(cursor.wrapping_add(2) & 0xFFF),
+            Keycode::Home => self.cursor = None,
+            Keycode::PageUp => self.scroll_memory(-(MEMORY_ROWS as i32)),
+            Keycode::PageDown => self.scroll_memory(MEMORY_ROWS as i32),
+            Keycode::I => self.memory_start = chip.index() & !(BYTES_PER_ROW - 1),
+            _ => {},
+        }
+        None
+    }
+
+    fn scroll_memory(&mut self, rows: i32) {
+        let last = 4096 - (MEMORY_ROWS * BYTES_PER_ROW) as i32;
+        let start = self.memory_start as i32 + rows * BYTES_PER_ROW as i32;
+        self.memory_start = start.clamp(0, last) as u16;
+    }
+
+    pub(crate) fn draw(&mut self, chip: &Chip8, paused: bool) {
+        self.canvas.set_draw_color(BACKGROUND);
+        self.canvas.clear();
+
+        self.draw_registers(chip, paused);
+        self.draw_disassembly(chip);
+        self.draw_memory(chip);
+        self.draw_sprite(chip);
+
+        let help = "S/F10 step  C/F5 continue  with Shift backwards  P pause  B breakpoint  \
+            Up/Down/Home cursor  PgUp/PgDn/wheel memory  I memory at I";
+        let (_, height) = self.canvas.output_size().unwrap();
+        self.text(REGISTERS_COLUMN, height.saturating_sub(LINE_HEIGHT + SCALE) / LINE_HEIGHT, help, TEXT);
+        self.canvas.present();
+    }
+
+    fn draw_registers(&mut self, chip: &Chip8, paused: bool) {
+        let x = REGISTERS_COLUMN;
+        let state = if chip.blocked_on_key() {
+            "WAITING FOR KEY"
+        } else if paused {
+            "PAUSED"
+        } else {
+            "RUNNING"
+        };
+        self.text(x, 1, state, HEADING);
+        self.text(x, 3, &format!("PC {:#05X}  I {:#05X}", chip.pc(), chip.index()), TEXT);
+        self.text(x, 4, &format!("DT {:#04X}  ST {:#04X}", chip.delay_timer(), chip.sound_timer()), TEXT);
+        for (row, registers) in chip.v().chunks(4).enumerate() {
+            let line: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(column, value)| format!("V{:X} {:02X}", row * 4 + column, value))
+                .collect();
+            self.text(x, 6 + row as u32, &line.join("  "), TEXT);
+        }
+
+        self.text(x, 11, "STACK", HEADING);
+        let stack = chip.stack();
+        if stack.is_empty() {
+            self.text(x, 12, "empty", TEXT);
+        }
+        for (depth, address) in stack.iter().rev().enumerate() {
+            self.text(x, 12 + depth as u32, &format!("{:2} {:#05X}", stack.len() - depth - 1, address), TEXT);
+        }
+
+        self.text(x, 30, "HISTORY", HEADING);
+        let history = format!("{} instructions back", self.history.depth(chip));
+        self.text(x, 31, &history, TEXT);
+    }
+
+    // Instructions around the cursor, or PC when there is none. Breakpoints
+    // are marked with a *, the instruction at PC is highlighted. ROM bytes
+    // the analysis found no code at are shown one at a time as data.
+    fn draw_disassembly(&mut self, chip: &Chip8) {
+        let x = DISASSEMBLY_COLUMN;
+        self.text(x, 1, "DISASSEMBLY", HEADING);
+
+        if self.analysis.rom() != chip.rom() {
+            self.analysis = analysis::analyze(chip.rom());
+        }
+        let pc = chip.pc();
+        let center = self.cursor.unwrap_or(pc);
+        let memory = chip.memory();
+        let rom = PROGRAM_OFFSET..PROGRAM_OFFSET + chip.rom().len() as u16;
+        let mut address = center.saturating_sub(DISASSEMBLY_LINES / 2 * 2);
+        for line in 0..DISASSEMBLY_LINES {
+            if address as usize + 1 >= memory.len() {
+                break;
+            }
+            let data = rom.contains(&address)
+                && address != pc
+                && self.cursor != Some(address)
+                && !self.analysis.is_instruction(address);
+            let listing = if data {
+                let byte = memory[address as usize];
+                format!("{:02X}   DB {:#04X}", byte, byte)
+            } else {
+                let opcode = u16::from_be_bytes([memory[address as usize], memory[address as usize + 1]]);
+                format!("{:04X} {}", opcode, disasm::disassemble(opcode))
+            };
+            let marker = match (self.cursor == Some(address), chip.breakpoints.contains(&address)) {
+                (true, true) => ">*",
+                (true, false) => "> ",
+                (false, true) => " *",
+                (false, false) => "  ",
+            };
+            let text = format!("{}{:#05X} {}", marker, address, listing);
+            let y = 3 + line as u32;
+            if address == pc {
+                self.label(x, y, &text, HIGHLIGHT, BACKGROUND);
+            } else if chip.breakpoints.contains(&address) {
+                self.text(x, y, &text, BREAKPOINT);
+            } else {
+                self.text(x, y, &text, TEXT);
+            }
+            address += if data { 1 } else { 2 };
+        }
+    }
+
+    // Hex dump with the bytes a sprite at I would use highlighted
+    fn draw_memory(&mut self, chip: &Chip8) {
+        let x = MEMORY_COLUMN;
+        self.text(x, 1, "MEMORY", HEADING);
+
+        let memory = chip.memory();
+        let sprite = chip.index()..chip.index().saturating_add(SPRITE_ROWS);
+        for row in 0..MEMORY_ROWS {
+            let address = self.memory_start + row * BYTES_PER_ROW;
+            let y = 3 + row as u32;
+            self.text(x, y, &format!("{:#05X}", address), HEADING);
+            for column in 0..BYTES_PER_ROW {
+                let byte_address = address + column;
+                let text = format!("{:02X}", memory[byte_address as usize]);
+                let byte_x = x + 6 + column as u32 * 3;
+                if sprite.contains(&byte_address) {
+                    self.label(byte_x, y, &text, HIGHLIGHT, BACKGROUND);
+                } else {
+                    self.text(byte_x, y, &text, TEXT);
+                }
+            }
+        }
+    }
+
+    // The 8 pixel wide sprite rows starting at I, as DXYN would draw them
+    fn draw_sprite(&mut self, chip: &Chip8) {
+        let x = MEMORY_COLUMN + 6 + BYTES_PER_ROW as u32 * 3 + 2;
+        self.text(x, 1, "SPRITE AT I", HEADING);
+
+        let left = (x * CHAR_WIDTH) as i32;
+        let top = (3 * LINE_HEIGHT) as i32;
+        let memory = chip.memory();
+        self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
+        let size = 8 * SPRITE_PIXEL;
+        self.canvas.fill_rect(Rect::new(left, top, size, SPRITE_ROWS as u32 * SPRITE_PIXEL)).unwrap();
+
+        self.canvas.set_draw_color(TEXT);
+        for row in 0..SPRITE_ROWS {
+            let Some(&bits) = memory.get(chip.index() as usize + row as usize) else {
+                break;
+            };
+            for column in 0..8 {
+                if bits & (0x80 >> column) != 0 {
+                    let rect = Rect::new(
+                        left + (column * SPRITE_PIXEL) as i32,
+                        top + (row as u32 * SPRITE_PIXEL) as i32,
+                        SPRITE_PIXEL,
+                        SPRITE_PIXEL,
+                    );
+                    self.canvas.fill_rect(rect).unwrap();
+                }
+            }
+        }
+    }
+
+    // Text at a character column and line
+    fn text(&mut self, column: u32, line: u32, text: &str, color: Color) {
+        self.label(column, line, text, BACKGROUND, color);
+    }
+
+    fn label(&mut self, column: u32, line: u32, text: &str, background: Color, foreground: Color) {
+        draw_scaled(&mut self.canvas, column * CHAR_WIDTH, line * LINE_HEIGHT, SCALE, text, background, foreground);
+    }
+}
@@ -0,0 +1,114 @@
+// Turns opcodes into the mnemonics from Cowgod's CHIP-8 reference, with the
+// SUPER-CHIP additions. Anything that isn't an instruction is shown as data.
+pub(crate) fn disassemble(opcode: u16) -> String {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let nn = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+
+    match (opcode >> 12, x, y, n) {
+        (0x0, 0x0, 0xC, _) => format!("SCD {}", n),
+        (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
+        (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
+        (0x0, 0x0, 0xF, 0xB) => "SCR".to_string(),
+        (0x0, 0x0, 0xF, 0xC) => "SCL".to_string(),
+        (0x0, 0x0, 0xF, 0xD) => "EXIT".to_string(),
+        (0x0, 0x0, 0xF, 0xE) => "LOW".to_string(),
+        (0x0, 0x0, 0xF, 0xF) => "HIGH".to_string(),
+        (0x0, _, _, _) => format!("SYS {:#05X}", nnn),
+        (0x1, _, _, _) => format!("JP {:#05X}", nnn),
+        (0x2, _, _, _) => format!("CALL {:#05X}", nnn),
+        (0x3, _, _, _) => format!("SE V{:X}, {:#04X}", x, nn),
+        (0x4, _, _, _) => format!("SNE V{:X}, {:#04X}", x, nn),
+        (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+        (0x6, _, _, _) => format!("LD V{:X}, {:#04X}", x, nn),
+        (0x7, _, _, _) => format!("ADD V{:X}, {:#04X}", x, nn),
+        (0x8, _, _, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x6) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, _, _, _) => format!("LD I, {:#05X}", nnn),
+        (0xB, _, _, _) => format!("JP V0, {:#05X}", nnn),
+        (0xC, _, _, _) => format!("RND V{:X}, {:#04X}", x, nn),
+        (0xD, _, _, _) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+        (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+        (0xF, _, 0x3, 0x0) => format!("LD HF, V{:X}", x),
+        (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+        (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+        (0xF, _, 0x7, 0x5) => format!("LD R, V{:X}", x),
+        (0xF, _, 0x8, 0x5) => format!("LD V{:X}, R", x),
+        _ => format!("DW {:#06X}", opcode),
+    }
+}
+
+// A data byte as a row of sprite pixels, # for the bits that are set
+pub(crate) fn sprite_row(byte: u8) -> String {
+    (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect()
+}
+
+// The opcode pattern an instruction belongs to, like "8XY4", for grouping
+// instructions by what they do
+pub(crate) fn pattern(opcode: u16) -> &'static str {
+    match (opcode >> 12, (opcode >> 8) & 0xF, (opcode >> 4) & 0xF, opcode & 0xF) {
+        (0x0, 0x0, 0xC, _) => "00CN",
+        (0x0, 0x0, 0xE, 0x0) => "00E0",
+        (0x0, 0x0, 0xE, 0xE) => "00EE",
+        (0x0, 0x0, 0xF, 0xB) => "00FB",
+        (0x0, 0x0, 0xF, 0xC) => "00FC",
+        (0x0, 0x0, 0xF, 0xD) => "00FD",
+        (0x0, 0x0, 0xF, 0xE) => "00FE",
+        (0x0, 0x0, 0xF, 0xF) => "00FF",
+        (0x0, _, _, _) => "0NNN",
+        (0x1, _, _, _) => "1NNN",
+        (0x2, _, _, _) => "2NNN",
+        (0x3, _, _, _) => "3XNN",
+        (0x4, _, _, _) => "4XNN",
+        (0x5, _, _, 0x0) => "5XY0",
+        (0x6, _, _, _) => "6XNN",
+        (0x7, _, _, _) => "7XNN",
+        (0x8, _, _, 0x0) => "8XY0",
+        (0x8, _, _, 0x1) => "8XY1",
+        (0x8, _, _, 0x2) => "8XY2",
+        (0x8, _, _, 0x3) => "8XY3",
+        (0x8, _, _, 0x4) => "8XY4",
+        (0x8, _, _, 0x5) => "8XY5",
+        (0x8, _, _, 0x6) => "8XY6",
+        (0x8, _, _, 0x7) => "8XY7",
+        (0x8, _, _, 0xE) => "8XYE",
+        (0x9, _, _, 0x0) => "9XY0",
+        (0xA, _, _, _) => "ANNN",
+        (0xB, _, _, _) => "BNNN",
+        (0xC, _, _, _) => "CXNN",
+        (0xD, _, _, _) => "DXYN",
+        (0xE, _, 0x9, 0xE) => "EX9E",
+        (0xE, _, 0xA, 0x1) => "EXA1",
+        (0xF, _, 0x0, 0x7) => "FX07",
+        (0xF, _, 0x0, 0xA) => "FX0A",
+        (0xF, _, 0x1, 0x5) => "FX15",
+        (0xF, _, 0x1, 0x8) => "FX18",
+        (0xF, _, 0x1, 0xE) => "FX1E",
+        (0xF, _, 0x2, 0x9) => "FX29",
+        (0xF, _, 0x3, 0x0) => "FX30",
+        (0xF, _, 0x3, 0x3) => "FX33",
+        (0xF, _, 0x5, 0x5) => "FX55",
+        (0xF, _, 0x6, 0x5) => "FX65",
+        (0xF, _, 0x7, 0x5) => "FX75",
+        (0xF, _, 0x8, 0x5) => "FX85",
+        _ => "data",
+    }
+}
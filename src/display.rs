@@ -0,0 +1,35 @@
+use sdl3::rect::Rect;
+
+// How the emulated screen is fitted into a window of arbitrary size
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Stretch {
+    // Largest whole multiple of the screen size, crisp but possibly with borders
+    Integer,
+    // As large as possible while keeping the 2:1 aspect ratio
+    Aspect,
+}
+
+// Where to draw a `source` sized screen inside an `output` sized window,
+// centered with the rest left as letterbox bars.
+pub(crate) fn destination_rect(output: (u32, u32), source: (u32, u32), stretch: Stretch) -> Rect {
+    let (output_width, output_height) = output;
+    let (source_width, source_height) = source;
+
+    let (width, height) = match stretch {
+        Stretch::Integer => {
+            let scale = (output_width / source_width).min(output_height / source_height).max(1);
+            (source_width * scale, source_height * scale)
+        },
+        Stretch::Aspect => {
+            if output_width * source_height > output_height * source_width {
+                (output_height * source_width / source_height, output_height)
+            } else {
+                (output_width, output_width * source_height / source_width)
+            }
+        },
+    };
+
+    let x = (output_width as i32 - width as i32) / 2;
+    let y = (output_height as i32 - height as i32) / 2;
+    Rect::new(x, y, width, height)
+}
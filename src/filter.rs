@@ -0,0 +1,123 @@
+use crate::cpu::{HEIGHT, WIDTH};
+
+// Brightness of every pixel, 0 is background and 255 fully lit
+pub(crate) type Frame = [[u8; WIDTH]; HEIGHT];
+
+// Ways of hiding the flicker caused by sprites being XOR-drawn: a moving
+// sprite is erased and redrawn, so it is missing from some frames.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum FilterMode {
+    None,
+    // Pixels that go dark fade out over this many frames, like phosphor
+    Fade(u8),
+    // Average of the current and the previous frame
+    Blend,
+    // Only show frames that were finished when the game cleared the screen,
+    // falling back to the live screen for frames without a 00E0
+    Buffered,
+}
+
+impl FilterMode {
+    pub(crate) fn parse(spec: &str) -> Option<Self> {
+        match spec.split_once(':') {
+            Some(("fade", frames)) => frames.parse().ok().filter(|&frames| frames > 0).map(FilterMode::Fade),
+            None => match spec {
+                "none" => Some(FilterMode::None),
+                "fade" => Some(FilterMode::Fade(4)),
+                "blend" => Some(FilterMode::Blend),
+                "buffered" => Some(FilterMode::Buffered),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// Turns the emulated screen into the frame that is actually shown. Runs on
+// the CPU so it works with the software renderer.
+pub(crate) struct DisplayFilter {
+    mode: FilterMode,
+    output: Frame,
+    previous: [[bool; WIDTH]; HEIGHT],
+}
+
+impl DisplayFilter {
+    pub(crate) fn new(mode: FilterMode) -> Self {
+        Self {
+            mode,
+            output: [[0; WIDTH]; HEIGHT],
+            previous: [[false; WIDTH]; HEIGHT],
+        }
+    }
+
+    pub(crate) fn output(&self) -> &Frame {
+        &self.output
+    }
+
+    // Feeds in the current screen after `frames` display frames have passed,
+    // along with the screen from before the last 00E0 if there was one.
+    // Returns whether the output changed.
+    pub(crate) fn update(
+        &mut self,
+        screen: &[[bool; WIDTH]; HEIGHT],
+        cleared: Option<&[[bool; WIDTH]; HEIGHT]>,
+        dirty: bool,
+        frames: u64,
+    ) -> bool {
+        match self.mode {
+            FilterMode::None => {
+                if dirty {
+                    self.show(screen);
+                }
+                dirty
+            },
+            FilterMode::Fade(length) => {
+                let decay = (255 / u64::from(length)).max(1).saturating_mul(frames).min(255) as u8;
+                let mut changed = false;
+                for (output_row, row) in self.output.iter_mut().zip(screen.iter()) {
+                    for (output, lit) in output_row.iter_mut().zip(row.iter()) {
+                        let value = if *lit { 255 } else { output.saturating_sub(decay) };
+                        changed |= value != *output;
+                        *output = value;
+                    }
+                }
+                changed
+            },
+            FilterMode::Blend => {
+                if frames == 0 {
+                    return false;
+                }
+                let mut changed = false;
+                let rows = self.output.iter_mut().zip(screen.iter().zip(self.previous.iter()));
+                for (output_row, (row, previous_row)) in rows {
+                    for (output, (lit, was_lit)) in output_row.iter_mut().zip(row.iter().zip(previous_row.iter())) {
+                        let value = match (*lit, *was_lit) {
+                            (true, true) => 255,
+                            (false, false) => 0,
+                            _ => 128,
+                        };
+                        changed |= value != *output;
+                        *output = value;
+                    }
+                }
+                self.previous = *screen;
+                changed
+            },
+            FilterMode::Buffered => {
+                if frames == 0 {
+                    return false;
+                }
+                self.show(cleared.unwrap_or(screen));
+                true
+            },
+        }
+    }
+
+    fn show(&mut self, screen: &[[bool; WIDTH]; HEIGHT]) {
+        for (output_row, row) in self.output.iter_mut().zip(screen.iter()) {
+            for (output, lit) in output_row.iter_mut().zip(row.iter()) {
+                *output = if *lit { 255 } else { 0 };
+            }
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use sdl3::event::Event;
+use sdl3::gamepad::{Axis, Button, Gamepad};
+use sdl3::sys::joystick::SDL_JoystickID as JoystickId;
+use sdl3::GamepadSubsystem;
+use crate::config::ConfigFile;
+use crate::overlay::Messages;
+
+// How far a stick has to be pushed before it counts as a press
+const AXIS_THRESHOLD: i16 = 16_000;
+
+// Used when neither the config file nor a built-in profile says otherwise.
+// The D-pad and left stick cover 5/7/8/9, the common up/left/down/right.
+const DEFAULT_MAPPING: [(usize, &str); 6] = [
+    (0x5, "dpup, lefty-"),
+    (0x7, "dpleft, leftx-"),
+    (0x8, "dpdown, lefty+"),
+    (0x9, "dpright, leftx+"),
+    (0x6, "a"),
+    (0x4, "b"),
+];
+
+// Built-in profiles for games whose controls don't fit the default mapping,
+// by ROM name
+const PROFILES: [(&str, &[(usize, &str)]); 3] = [
+    ("pong", &[(0x1, "dpup, lefty-"), (0x4, "dpdown, lefty+")]),
+    ("brix", &[(0x4, "dpleft, leftx-"), (0x6, "dpright, leftx+")]),
+    ("breakout", &[(0x4, "dpleft, leftx-"), (0x6, "dpright, leftx+")]),
+];
+
+// A button, or one direction of an axis. Triggers only have the positive one.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Control {
+    Button(Button),
+    AxisNegative(Axis),
+    AxisPositive(Axis),
+}
+
+impl Control {
+    // Names are the ones from SDL gamepad mapping strings ("a", "dpup",
+    // "leftx"), axes take a + or - for the direction
+    fn parse(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        if let Some(axis) = name.strip_suffix('-') {
+            return Axis::from_string(axis).map(Control::AxisNegative);
+        }
+        let axis = name.strip_suffix('+').unwrap_or(&name);
+        Axis::from_string(axis)
+            .map(Control::AxisPositive)
+            .or_else(|| Button::from_string(&name).map(Control::Button))
+    }
+}
+
+// Which controls press which hex key
+struct PadMap {
+    controls: Vec<(Control, usize)>,
+}
+
+impl PadMap {
+    // `pad.<hex> = control, control...` entries in the config file replace
+    // the default mapping. A ROM gets its own profile from
+    // `<rom>.pad.<hex>` entries or the built-in ones, replacing the rest.
+    fn load(config_file: &ConfigFile, rom_name: &str, messages: &mut Messages) -> Self {
+        let configured = |prefix: &str| -> Vec<(usize, String)> {
+            (0..16)
+                .filter_map(|index| {
+                    config_file.get::<String>(&format!("{}pad.{:x}", prefix, index)).map(|names| (index, names))
+                })
+                .collect()
+        };
+
+        let mut entries = configured(&format!("{}.", rom_name));
+        if entries.is_empty() {
+            entries = PROFILES
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(rom_name))
+                .map(|(_, profile)| profile.iter().map(|(index, names)| (*index, names.to_string())).collect())
+                .unwrap_or_default();
+        }
+        if entries.is_empty() {
+            entries = configured("");
+        }
+        if entries.is_empty() {
+            entries = DEFAULT_MAPPING.iter().map(|(index, names)| (*index, names.to_string())).collect();
+        }
+
+        let mut controls = Vec::new();
+        for (index, names) in entries {
+            for name in names.split(',').filter(|name| !name.trim().is_empty()) {
+                match Control::parse(name) {
+                    Some(control) => controls.push((control, index)),
+                    None => messages.error(format!("Unknown gamepad control {}", name.trim())),
+                }
+            }
+        }
+        Self { controls }
+    }
+
+    fn keys(&self, control: Control) -> impl Iterator<Item = usize> + '_ {
+        self.controls.iter().filter(move |(bound, _)| *bound == control).map(|(_, index)| *index)
+    }
+}
+
+// Opens gamepads as they are plugged in and turns their input into hex key
+// presses and releases
+pub(crate) struct Gamepads {
+    subsystem: GamepadSubsystem,
+    map: PadMap,
+    open: HashMap<u32, Gamepad>,
+    // Controls currently held, by gamepad, so unplugging releases them
+    held: Vec<(u32, Control)>,
+}
+
+impl Gamepads {
+    pub(crate) fn new(
+        subsystem: GamepadSubsystem,
+        config_file: &ConfigFile,
+        rom_name: &str,
+        messages: &mut Messages,
+    ) -> Self {
+        // Gamepads that were plugged in before now don't send an added event
+        let open = subsystem
+            .gamepads()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| subsystem.open(id).ok().map(|gamepad| (id.0, gamepad)))
+            .collect();
+        Self {
+            subsystem,
+            map: PadMap::load(config_file, rom_name, messages),
+            open,
+            held: Vec::new(),
+        }
+    }
+
+    // Switches to the mapping for another ROM
+    pub(crate) fn load_map(&mut self, config_file: &ConfigFile, rom_name: &str, messages: &mut Messages) {
+        self.map = PadMap::load(config_file, rom_name, messages);
+    }
+
+    // Handles gamepad events, returning the hex keys that went down (true)
+    // or up (false). Other events give nothing.
+    pub(crate) fn handle_event(&mut self, event: &Event, messages: &mut Messages) -> Vec<(usize, bool)> {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                if self.open.contains_key(&which) {
+                    return Vec::new();
+                }
+                match self.subsystem.open(JoystickId(which)) {
+                    Ok(gamepad) => {
+                        messages.info(format!("Gamepad connected: {}", gamepad.name().unwrap_or_default()));
+                        self.open.insert(which, gamepad);
+                    },
+                    Err(e) => messages.error(format!("Unable to open gamepad: {}", e)),
+                }
+                Vec::new()
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.open.remove(&which);
+                let released: Vec<Control> = self.held
+                    .iter()
+                    .filter(|(pad, _)| *pad == which)
+                    .map(|(_, control)| *control)
+                    .collect();
+                released.into_iter().flat_map(|control| self.set(which, control, false)).collect()
+            },
+            Event::ControllerButtonDown { which, button, .. } => self.set(which, Control::Button(button), true),
+            Event::ControllerButtonUp { which, button, .. } => self.set(which, Control::Button(button), false),
+            Event::ControllerAxisMotion { which, axis, value, .. } => {
+                let mut changes = self.set(which, Control::AxisNegative(axis), value < -AXIS_THRESHOLD);
+                changes.extend(self.set(which, Control::AxisPositive(axis), value > AXIS_THRESHOLD));
+                changes
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    // Only changes of a control's state produce key events, sticks send lots
+    // of motion while staying on the same side of the threshold
+    fn set(&mut self, pad: u32, control: Control, pressed: bool) -> Vec<(usize, bool)> {
+        let position = self.held.iter().position(|held| *held == (pad, control));
+        match (position, pressed) {
+            (None, true) => self.held.push((pad, control)),
+            (Some(position), false) => {
+                self.held.remove(position);
+            },
+            _ => return Vec::new(),
+        }
+        self.map.keys(control).map(|index| (index, pressed)).collect()
+    }
+}
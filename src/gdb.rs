@@ -0,0 +1,460 @@
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use crate::cpu::Chip8;
+use crate::history::History;
+use crate::runner::{self, FRAMES_PER_SECOND};
+use crate::timing::Timing;
+
+// Describes the registers in the order of the `g` packet. GDB has no
+// CHIP-8 architecture, so this is mostly for tools and scripts that
+// read the register names from the target.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="sp" bitsize="16" type="uint16"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// Register numbers after V0-VF
+const REGISTER_I: usize = 16;
+const REGISTER_SP: usize = 17;
+const REGISTER_PC: usize = 18;
+const REGISTER_DT: usize = 19;
+const REGISTER_ST: usize = 20;
+const REGISTER_COUNT: usize = 21;
+
+// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// Stop reply for reverse execution running out of history
+const HISTORY_START: &str = "T05replaylog:begin;";
+
+// Sent by GDB to interrupt a running target
+const INTERRUPT: u8 = 0x03;
+
+// Serves the GDB remote serial protocol on localhost, one client at a time,
+// until a client kills the target. Port 0 picks a free port. The machine
+// starts stopped and runs on the headless virtual clock when continued.
+pub(crate) fn serve(chip: &mut Chip8, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("GDB server listening on {}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        stream.set_nodelay(true)?;
+        println!("GDB client connected from {}", stream.peer_addr()?);
+        let mut session = Session::new(stream, chip)?;
+        let result = session.run(chip);
+        session.history.close(chip);
+        match result {
+            Ok(true) => return Ok(()),
+            Ok(false) => println!("GDB client detached"),
+            Err(e) => eprintln!("GDB connection lost: {}", e),
+        }
+    }
+    Ok(())
+}
+
+struct Session {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // Turned off by QStartNoAckMode
+    acks: bool,
+    // For the reverse step and continue packets
+    history: History,
+}
+
+impl Session {
+    fn new(stream: TcpStream, chip: &mut Chip8) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            acks: true,
+            history: History::new(chip),
+        })
+    }
+
+    // Answers packets until the client goes away. Returns whether the
+    // target was killed, which ends the server.
+    fn run(&mut self, chip: &mut Chip8) -> io::Result<bool> {
+        while let Some(packet) = self.receive()? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'?') => stop_reply(SIGTRAP),
+                Some(b'c') | Some(b's') if packet.len() > 1 && !parse_hex(&packet[1..]).is_some_and(valid_pc) => {
+                    "E01".to_string()
+                },
+                Some(b'c') => {
+                    if let Some(address) = parse_hex(&packet[1..]) {
+                        chip.set_pc(address as u16);
+                    }
+                    let signal = self.resume(chip)?;
+                    stop_reply(signal)
+                },
+                Some(b's') => {
+                    if let Some(address) = parse_hex(&packet[1..]) {
+                        chip.set_pc(address as u16);
+                    }
+                    chip.step();
+                    self.history.record(chip);
+                    stop_reply(take_error(chip).unwrap_or(SIGTRAP))
+                },
+                Some(b'b') if packet == "bs" => if self.history.step_back(chip) {
+                    stop_reply(SIGTRAP)
+                } else {
+                    HISTORY_START.to_string()
+                },
+                Some(b'b') if packet == "bc" => if self.history.reverse_continue(chip) {
+                    stop_reply(SIGTRAP)
+                } else {
+                    HISTORY_START.to_string()
+                },
+                Some(b'g') => encode_hex(&registers(chip)),
+                // Edits can't be replayed, so the history starts over after them
+                Some(b'G') => match decode_hex(&packet[1..]).and_then(|bytes| write_registers(chip, &bytes)) {
+                    Some(()) => {
+                        self.history.restart(chip);
+                        "OK".to_string()
+                    },
+                    None => "E01".to_string(),
+                },
+                Some(b'p') => match parse_hex(&packet[1..]).and_then(|number| register(chip, number)) {
+                    Some(bytes) => encode_hex(&bytes),
+                    None => "E01".to_string(),
+                },
+                Some(b'P') => match write_register_packet(chip, &packet[1..]) {
+                    Some(()) => {
+                        self.history.restart(chip);
+                        "OK".to_string()
+                    },
+                    None => "E01".to_string(),
+                },
+                Some(b'm') => match read_memory(chip, &packet[1..]) {
+                    Some(bytes) => encode_hex(&bytes),
+                    None => "E01".to_string(),
+                },
+                Some(b'M') => match write_memory(chip, &packet[1..]) {
+                    Some(()) => {
+                        self.history.restart(chip);
+                        "OK".to_string()
+                    },
+                    None => "E01".to_string(),
+                },
+                Some(b'Z') | Some(b'z') => match breakpoint(chip, &packet) {
+                    Some(true) => "OK".to_string(),
+                    // Other kinds of breakpoints and watchpoints
+                    Some(false) => String::new(),
+                    None => "E01".to_string(),
+                },
+                Some(b'H') => "OK".to_string(),
+                Some(b'k') => return Ok(true),
+                Some(b'D') => {
+                    self.send("OK")?;
+                    return Ok(false);
+                },
+                _ => query(&packet, &mut self.acks),
+            };
+            self.send(&reply)?;
+        }
+        Ok(false)
+    }
+
+    // Runs until a breakpoint, an error in the ROM or an interrupt from the
+    // client, and returns the signal to report
+    fn resume(&mut self, chip: &mut Chip8) -> io::Result<u8> {
+        let mut timing = Timing::new_virtual(runner::systems(chip));
+        let frame_duration = Duration::from_secs(1) / FRAMES_PER_SECOND;
+        chip.resume();
+        loop {
+            runner::execute_instructions(chip, timing.advance(frame_duration), false);
+            self.history.record(chip);
+            if chip.take_breakpoint_hit() {
+                return Ok(SIGTRAP);
+            }
+            if let Some(signal) = take_error(chip) {
+                return Ok(signal);
+            }
+            if self.interrupted()? {
+                return Ok(SIGINT);
+            }
+        }
+    }
+
+    // Checks for the interrupt byte without waiting
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let stream = self.reader.get_mut();
+        stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.reader.read(&mut byte);
+        self.reader.get_mut().set_nonblocking(false)?;
+        match result {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(byte[0] == INTERRUPT),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Reads the next packet, skipping acks and stray interrupts. Returns None
+    // once the client has closed the connection.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            if self.reader.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] != b'$' {
+                continue;
+            }
+
+            let mut data = Vec::new();
+            loop {
+                self.reader.read_exact(&mut byte)?;
+                match byte[0] {
+                    b'#' => break,
+                    b'}' => {
+                        self.reader.read_exact(&mut byte)?;
+                        data.push(byte[0] ^ 0x20);
+                    },
+                    other => data.push(other),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|text| u8::from_str_radix(text, 16).ok());
+            let valid = expected == Some(data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)));
+            if self.acks {
+                self.writer.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut escaped = Vec::with_capacity(data.len());
+        for &byte in data.as_bytes() {
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                escaped.extend_from_slice(&[b'}', byte ^ 0x20]);
+            } else {
+                escaped.push(byte);
+            }
+        }
+        let checksum = escaped.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        let mut packet = vec![b'$'];
+        packet.extend_from_slice(&escaped);
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+        self.writer.write_all(&packet)?;
+        self.writer.flush()
+    }
+}
+
+// General queries and anything else. Unsupported packets get an empty reply.
+fn query(packet: &str, acks: &mut bool) -> String {
+    if packet.starts_with("qSupported") {
+        return "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;ReverseStep+;ReverseContinue+".to_string();
+    }
+    if packet == "QStartNoAckMode" {
+        *acks = false;
+        return "OK".to_string();
+    }
+    if packet == "qAttached" {
+        return "1".to_string();
+    }
+    if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        return match parse_address_length(range, ',') {
+            Some((offset, length)) => {
+                let xml = TARGET_XML.as_bytes();
+                let start = offset.min(xml.len());
+                let end = start.saturating_add(length).min(xml.len());
+                let prefix = if end == xml.len() { 'l' } else { 'm' };
+                format!("{}{}", prefix, &TARGET_XML[start..end])
+            },
+            None => "E01".to_string(),
+        };
+    }
+    String::new()
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+// Errors in the ROM stop it like an illegal instruction would
+fn take_error(chip: &mut Chip8) -> Option<u8> {
+    let error = chip.take_error()?;
+    eprintln!("{}", error);
+    Some(SIGILL)
+}
+
+fn register_bytes() -> usize {
+    (0..REGISTER_COUNT).map(register_size).sum()
+}
+
+fn register_size(number: usize) -> usize {
+    match number {
+        REGISTER_I | REGISTER_SP | REGISTER_PC => 2,
+        _ => 1,
+    }
+}
+
+// A register in target byte order, little endian like GDB expects
+fn register(chip: &Chip8, number: usize) -> Option<Vec<u8>> {
+    let bytes = match number {
+        0..=15 => vec![chip.v()[number]],
+        REGISTER_I => chip.index().to_le_bytes().to_vec(),
+        REGISTER_SP => (chip.stack().len() as u16).to_le_bytes().to_vec(),
+        REGISTER_PC => chip.pc().to_le_bytes().to_vec(),
+        REGISTER_DT => vec![chip.delay_timer()],
+        REGISTER_ST => vec![chip.sound_timer()],
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+fn registers(chip: &Chip8) -> Vec<u8> {
+    (0..REGISTER_COUNT).filter_map(|number| register(chip, number)).flatten().collect()
+}
+
+// SP can't be written, the stack contents would not follow
+fn set_register(chip: &mut Chip8, number: usize, bytes: &[u8]) -> Option<()> {
+    if bytes.len() != register_size(number) {
+        return None;
+    }
+    match number {
+        0..=15 => chip.set_v(number, bytes[0]),
+        REGISTER_I => chip.set_index(u16::from_le_bytes([bytes[0], bytes[1]])),
+        REGISTER_SP => {},
+        REGISTER_PC => {
+            let pc = u16::from_le_bytes([bytes[0], bytes[1]]);
+            if !valid_pc(pc as usize) {
+                return None;
+            }
+            chip.set_pc(pc);
+        },
+        REGISTER_DT => chip.set_delay_timer(bytes[0]),
+        REGISTER_ST => chip.set_sound_timer(bytes[0]),
+        _ => return None,
+    }
+    Some(())
+}
+
+// Writes nothing unless every register can be written
+fn write_registers(chip: &mut Chip8, bytes: &[u8]) -> Option<()> {
+    if bytes.len() != register_bytes() {
+        return None;
+    }
+    let mut values = Vec::new();
+    let mut offset = 0;
+    for number in 0..REGISTER_COUNT {
+        let size = register_size(number);
+        values.push(&bytes[offset..offset + size]);
+        offset += size;
+    }
+    let pc = values[REGISTER_PC];
+    if !valid_pc(u16::from_le_bytes([pc[0], pc[1]]) as usize) {
+        return None;
+    }
+    for (number, value) in values.into_iter().enumerate() {
+        set_register(chip, number, value)?;
+    }
+    Some(())
+}
+
+// Instructions are two bytes, both of them have to be in memory. Odd
+// addresses are fine, CHIP-8 doesn't align instructions.
+fn valid_pc(address: usize) -> bool {
+    address.saturating_add(1) < 4096
+}
+
+// `P n=value`
+fn write_register_packet(chip: &mut Chip8, arguments: &str) -> Option<()> {
+    let (number, value) = arguments.split_once('=')?;
+    set_register(chip, parse_hex(number)?, &decode_hex(value)?)
+}
+
+// `m addr,length`
+fn read_memory(chip: &Chip8, arguments: &str) -> Option<Vec<u8>> {
+    let (address, length) = parse_address_length(arguments, ',')?;
+    chip.memory().get(address..address.checked_add(length)?).map(<[u8]>::to_vec)
+}
+
+// `M addr,length:data`
+fn write_memory(chip: &mut Chip8, arguments: &str) -> Option<()> {
+    let (range, data) = arguments.split_once(':')?;
+    let (address, length) = parse_address_length(range, ',')?;
+    let data = decode_hex(data)?;
+    if data.len() != length {
+        return None;
+    }
+    chip.memory_mut().get_mut(address..address.checked_add(length)?)?.copy_from_slice(&data);
+    Some(())
+}
+
+// `Z0,addr,kind` and `z0,addr,kind`. Returns Some(false) for breakpoint
+// types other than software breakpoints.
+fn breakpoint(chip: &mut Chip8, packet: &str) -> Option<bool> {
+    let mut fields = packet[1..].split(',');
+    if fields.next()? != "0" {
+        return Some(false);
+    }
+    let address = parse_hex(fields.next()?)?;
+    if address >= chip.memory().len() {
+        return None;
+    }
+    if packet.starts_with('Z') {
+        chip.breakpoints.insert(address as u16);
+    } else {
+        chip.breakpoints.remove(&(address as u16));
+    }
+    Some(true)
+}
+
+fn parse_address_length(text: &str, separator: char) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(separator)?;
+    Some((parse_hex(address)?, parse_hex(length)?))
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(text.get(start..start + 2)?, 16).ok())
+        .collect()
+}
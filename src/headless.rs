@@ -0,0 +1,27 @@
+use std::time::Duration;
+use crate::cpu::Chip8;
+use crate::runner::{self, FRAMES_PER_SECOND};
+use crate::timing::Timing;
+
+// Runs the emulator without any output for a fixed number of display frames
+// on a virtual clock, so the result is the same on every machine.
+// `on_frame` is called once for every display frame. With `until_key_wait`
+// the run also ends at the first frame where the ROM is blocked on FX0A,
+// since nothing happens after that without input. Returns the frames run.
+pub(crate) fn run(chip: &mut Chip8, frames: u64, until_key_wait: bool, mut on_frame: impl FnMut(&Chip8)) -> u64 {
+    let mut timing = Timing::new_virtual(runner::systems(chip));
+    let frame_duration = Duration::from_secs(1) / FRAMES_PER_SECOND;
+
+    let mut frames_run = 0;
+    while frames_run < frames {
+        let frames_due = runner::execute_instructions(chip, timing.advance(frame_duration), false);
+        for _ in 0..frames_due.min(frames - frames_run) {
+            on_frame(chip);
+        }
+        frames_run += frames_due;
+        if until_key_wait && chip.blocked_on_key() {
+            break;
+        }
+    }
+    frames_run.min(frames)
+}
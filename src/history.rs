@@ -0,0 +1,172 @@
+use std::collections::VecDeque;
+use crate::cpu::{Chip8, Input, Snapshot};
+
+// Instructions between checkpoints
+const CHECKPOINT_INTERVAL: u64 = 1000;
+// Oldest checkpoints are dropped beyond this, a few minutes at normal speed
+const MAX_CHECKPOINTS: usize = 128;
+
+struct Checkpoint {
+    snapshot: Snapshot,
+    instructions: u64,
+    // Position in the input log of the first input after the snapshot
+    input: usize,
+}
+
+// Lets the debugger run backwards. The machine is snapshotted every few
+// instructions and every input is journaled, so any earlier instruction
+// can be reached by restoring a checkpoint and replaying forward to it.
+pub(crate) struct History {
+    checkpoints: VecDeque<Checkpoint>,
+    inputs: VecDeque<(u64, Input)>,
+    // Log position of the front of `inputs`
+    first_input: usize,
+}
+
+impl History {
+    pub(crate) fn new(chip: &mut Chip8) -> Self {
+        chip.record_inputs(true);
+        let mut history = Self {
+            checkpoints: VecDeque::new(),
+            inputs: VecDeque::new(),
+            first_input: 0,
+        };
+        history.checkpoint(chip);
+        history
+    }
+
+    pub(crate) fn close(self, chip: &mut Chip8) {
+        chip.record_inputs(false);
+    }
+
+    // Takes the inputs journaled since the last call and a checkpoint when
+    // one is due. Called regularly while the machine runs.
+    pub(crate) fn record(&mut self, chip: &mut Chip8) {
+        let inputs = chip.take_inputs();
+        if inputs.iter().any(|(_, input)| *input == Input::Reset) {
+            // Nothing before a reset or a new ROM can be replayed
+            self.restart(chip);
+            return;
+        }
+        self.inputs.extend(inputs);
+
+        let latest = self.checkpoints.back().map_or(0, |checkpoint| checkpoint.instructions);
+        if chip.instruction_count() >= latest + CHECKPOINT_INTERVAL {
+            self.checkpoint(chip);
+        }
+    }
+
+    // Forgets everything before the current state, for when the machine
+    // was changed behind the history's back
+    pub(crate) fn restart(&mut self, chip: &mut Chip8) {
+        chip.take_inputs();
+        self.first_input += self.inputs.len();
+        self.inputs.clear();
+        self.checkpoints.clear();
+        self.checkpoint(chip);
+    }
+
+    fn checkpoint(&mut self, chip: &mut Chip8) {
+        self.checkpoints.push_back(Checkpoint {
+            snapshot: chip.snapshot(),
+            instructions: chip.instruction_count(),
+            input: self.first_input + self.inputs.len(),
+        });
+        if self.checkpoints.len() > MAX_CHECKPOINTS {
+            self.checkpoints.pop_front();
+            let keep = self.checkpoints[0].input;
+            self.inputs.drain(..keep - self.first_input);
+            self.first_input = keep;
+        }
+    }
+
+    // How many instructions can be undone
+    pub(crate) fn depth(&self, chip: &Chip8) -> u64 {
+        let oldest = self.checkpoints.front().map_or(0, |checkpoint| checkpoint.instructions);
+        chip.instruction_count().saturating_sub(oldest)
+    }
+
+    // Undoes the last instruction. Returns false at the start of the history.
+    pub(crate) fn step_back(&mut self, chip: &mut Chip8) -> bool {
+        self.record(chip);
+        let current = chip.instruction_count();
+        let Some(index) = self.checkpoints.iter().rposition(|checkpoint| checkpoint.instructions < current) else {
+            return false;
+        };
+        let position = self.replay(chip, index, current - 1, |_| {});
+        self.truncate(chip, position);
+        true
+    }
+
+    // Goes back to the last time execution reached a breakpoint. Returns
+    // false when there was none, leaving the machine at the start of the
+    // history.
+    pub(crate) fn reverse_continue(&mut self, chip: &mut Chip8) -> bool {
+        self.record(chip);
+        // Searches one stretch between checkpoints at a time, newest first
+        let mut end = chip.instruction_count();
+        for index in (0..self.checkpoints.len()).rev() {
+            let start = self.checkpoints[index].instructions;
+            if start >= end {
+                continue;
+            }
+            let mut found = None;
+            self.replay(chip, index, end - 1, |chip| {
+                if chip.breakpoints.contains(&chip.pc()) {
+                    found = Some(chip.instruction_count());
+                }
+            });
+            if let Some(target) = found {
+                let position = self.replay(chip, index, target, |_| {});
+                self.truncate(chip, position);
+                return true;
+            }
+            end = start;
+        }
+
+        if let Some(oldest) = self.checkpoints.front() {
+            let start = oldest.instructions;
+            let position = self.replay(chip, 0, start, |_| {});
+            self.truncate(chip, position);
+        }
+        false
+    }
+
+    // Restores a checkpoint and runs forward until `target` instructions
+    // have been executed, calling `visit` at every instruction boundary on
+    // the way. Inputs recorded between two instructions are applied before
+    // the next one runs, those recorded after the target aren't. Returns the
+    // log position of the first input not applied.
+    fn replay(&self, chip: &mut Chip8, index: usize, target: u64, mut visit: impl FnMut(&Chip8)) -> usize {
+        let checkpoint = &self.checkpoints[index];
+        chip.restore(&checkpoint.snapshot);
+        let mut position = checkpoint.input;
+        let mut inputs = self.inputs.iter().skip(checkpoint.input - self.first_input).peekable();
+        loop {
+            visit(chip);
+            if chip.instruction_count() >= target {
+                break;
+            }
+            while let Some((_, input)) = inputs.next_if(|(at, _)| *at <= chip.instruction_count()) {
+                chip.replay_input(*input);
+                position += 1;
+            }
+            chip.replay_instruction();
+        }
+        // Errors were already reported the first time round
+        chip.take_error();
+        position
+    }
+
+    // Drops what lies after the current state at log `position`, it's
+    // about to be rewritten
+    fn truncate(&mut self, chip: &Chip8, position: usize) {
+        let current = chip.instruction_count();
+        while self.checkpoints.len() > 1
+            && self.checkpoints.back().is_some_and(|checkpoint| checkpoint.instructions > current || checkpoint.input > position)
+        {
+            self.checkpoints.pop_back();
+        }
+        self.inputs.truncate(position - self.first_input);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::config::ConfigFile;
+
+// The 16 key hex keypad laid over the left side of a QWERTY keyboard:
+//
+//   1 2 3 C        1 2 3 4
+//   4 5 6 D   <-   Q W E R
+//   7 8 9 E        A S D F
+//   A 0 B F        Z X C V
+const LAYOUT: [(&str, usize); 16] = [
+    ("1", 0x1), ("2", 0x2), ("3", 0x3), ("4", 0xC),
+    ("Q", 0x4), ("W", 0x5), ("E", 0x6), ("R", 0xD),
+    ("A", 0x7), ("S", 0x8), ("D", 0x9), ("F", 0xE),
+    ("Z", 0xA), ("X", 0x0), ("C", 0xB), ("V", 0xF),
+];
+
+// The hex keys in the order they sit on the keypad, row by row
+pub(crate) const KEYPAD_ROWS: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// What a key name refers to
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Binding {
+    // The physical position, so the default layout stays in the same place on
+    // AZERTY or Dvorak keyboards
+    Scancode,
+    // The symbol printed on the key
+    Keycode,
+}
+
+impl Binding {
+    pub(crate) fn parse(name: &str) -> Option<Self> {
+        match name {
+            "scancode" => Some(Binding::Scancode),
+            "keycode" => Some(Binding::Keycode),
+            _ => None,
+        }
+    }
+}
+
+// Key names bound to every hex key. Names are the ones SDL uses ("W", "Up",
+// "Keypad 8") and are matched without case.
+pub(crate) struct Keymap {
+    pub(crate) binding: Binding,
+    keys: Vec<(String, usize)>,
+}
+
+impl Keymap {
+    // The default layout, changed by `keys.<hex> = name, name...` entries in
+    // the config file. Entries prefixed with the ROM name, like
+    // `pong.keys.1 = Up`, only apply to that ROM and win over the others.
+    // `key_binding` and `<rom>.key_binding` pick scancodes or keycodes.
+    pub(crate) fn load(config_file: &ConfigFile, rom_name: &str) -> Self {
+        let binding = config_file.get::<String>(&format!("{}.key_binding", rom_name))
+            .or_else(|| config_file.get("key_binding"))
+            .and_then(|name| Binding::parse(&name))
+            .unwrap_or(Binding::Scancode);
+
+        let mut keys = Vec::new();
+        for index in 0..16 {
+            let names = config_file.get::<String>(&format!("{}.keys.{:x}", rom_name, index))
+                .or_else(|| config_file.get(&format!("keys.{:x}", index)));
+            match names {
+                Some(names) => keys.extend(
+                    names
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(|name| (name.to_string(), index)),
+                ),
+                None => keys.extend(
+                    LAYOUT
+                        .iter()
+                        .filter(|(_, layout_index)| *layout_index == index)
+                        .map(|(name, _)| (name.to_string(), index)),
+                ),
+            }
+        }
+
+        Self { binding, keys }
+    }
+
+    pub(crate) fn translate(&self, name: &str) -> Option<usize> {
+        self.keys
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, index)| *index)
+    }
+}
+
+// Counts the physical keys held down for every hex key, so letting go of one
+// of several keys bound to the same hex key doesn't release it
+pub(crate) struct HeldKeys([u8; 16]);
+
+impl HeldKeys {
+    pub(crate) fn new() -> Self {
+        Self([0; 16])
+    }
+
+    // Returns whether the hex key is down afterwards
+    pub(crate) fn update(&mut self, index: usize, pressed: bool) -> bool {
+        let count = &mut self.0[index];
+        *count = if pressed { count.saturating_add(1) } else { count.saturating_sub(1) };
+        *count > 0
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use sdl3::event::Event;
+use sdl3::keyboard::Keycode;
+use sdl3::pixels::Color;
+use sdl3::render::Canvas;
+use sdl3::video::Window;
+use sdl3::EventPump;
+use crate::romdb::{self, RomInfo};
+use crate::text::{draw_label, draw_text, LINE_HEIGHT, OVERLAY_SCALE};
+
+// Longest file name shown before it gets cut off
+const NAME_WIDTH: usize = 28;
+
+struct Entry {
+    path: PathBuf,
+    name: String,
+    // None for sources that can't be run
+    platform: Option<&'static str>,
+    // Shown instead of starting the file
+    unsupported: Option<&'static str>,
+    info: Option<&'static RomInfo>,
+}
+
+impl Entry {
+    fn new(path: PathBuf) -> Self {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let platform = if romdb::is_source(&path) {
+            None
+        } else {
+            fs::read(&path).ok().map(|rom| romdb::detect_platform(&path, &rom))
+        };
+        Self { name, platform, unsupported: romdb::unsupported(&path), info: romdb::lookup(&stem), path }
+    }
+}
+
+// Shows the ROMs in `directory` and hands the one picked to `start`. A ROM
+// that fails to start gets its error shown and another can be picked.
+// Returns false when the window is closed instead.
+pub(crate) fn run(
+    canvas: &mut Canvas<Window>,
+    event_pump: &mut EventPump,
+    directory: &Path,
+    mut start: impl FnMut(&Path) -> Result<(), String>,
+) -> bool {
+    let mut message = String::new();
+    let mut entries: Vec<Entry> = match fs::read_dir(directory) {
+        Ok(dir) => dir
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && romdb::is_rom_file(path))
+            .map(Entry::new)
+            .collect(),
+        Err(e) => {
+            message = format!("Unable to list {}: {}", directory.display(), e);
+            Vec::new()
+        },
+    };
+    entries.sort_by_key(|entry| entry.name.to_ascii_lowercase());
+
+    let mut selected = 0;
+    loop {
+        let rows = visible_rows(canvas);
+        draw(canvas, directory, &entries, selected, rows, &message);
+
+        let Some(event) = event_pump.wait_event_timeout(100) else {
+            continue;
+        };
+        match event {
+            Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return false,
+            Event::DropFile { filename, .. } => match start(Path::new(&filename)) {
+                Ok(()) => return true,
+                Err(e) => message = e,
+            },
+            Event::KeyDown { keycode: Some(key), .. } => {
+                let last = entries.len().saturating_sub(1);
+                match key {
+                    Keycode::Up => selected = selected.saturating_sub(1),
+                    Keycode::Down => selected = (selected + 1).min(last),
+                    Keycode::PageUp => selected = selected.saturating_sub(rows),
+                    Keycode::PageDown => selected = (selected + rows).min(last),
+                    Keycode::Home => selected = 0,
+                    Keycode::End => selected = last,
+                    Keycode::Return => match entries.get(selected) {
+                        Some(Entry { unsupported: Some(reason), .. }) => message = reason.to_string(),
+                        Some(entry) => match start(&entry.path) {
+                            Ok(()) => return true,
+                            Err(e) => message = e,
+                        },
+                        None => {},
+                    },
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+// List rows that fit between the title and the details at the bottom
+fn visible_rows(canvas: &Canvas<Window>) -> usize {
+    let (_, height) = canvas.output_size().unwrap();
+    (height / LINE_HEIGHT).saturating_sub(8).max(1) as usize
+}
+
+fn draw(canvas: &mut Canvas<Window>, directory: &Path, entries: &[Entry], selected: usize, rows: usize, message: &str) {
+    canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
+    canvas.clear();
+
+    let x = OVERLAY_SCALE;
+    let line = |row: usize| OVERLAY_SCALE + row as u32 * LINE_HEIGHT;
+    draw_text(canvas, x, line(0), &format!("ROMs in {}", directory.display()));
+
+    if entries.is_empty() {
+        draw_text(canvas, x, line(2), "No ROMs found");
+    }
+
+    // Keep the selection in view, scrolling a page at a time
+    let first = selected / rows * rows;
+    for (row, entry) in entries.iter().enumerate().skip(first).take(rows) {
+        let name: String = entry.name.chars().take(NAME_WIDTH).collect();
+        let platform = match (entry.platform, entry.unsupported) {
+            (None, _) => "source".to_string(),
+            (Some(platform), Some(_)) => format!("{} unsupported", platform),
+            (Some(platform), None) => platform.to_string(),
+        };
+        let text = format!("{:<width$} {}", name, platform, width = NAME_WIDTH);
+        let y = line(2 + row - first);
+        if row == selected {
+            draw_label(canvas, x, y, &text, Color::RGBA(255, 255, 0, 255), Color::RGBA(0, 0, 0, 255));
+        } else {
+            draw_text(canvas, x, y, &text);
+        }
+    }
+
+    let details = line(3 + rows);
+    if let Some(entry) = entries.get(selected) {
+        let info = entry.info;
+        draw_text(canvas, x, details, info.map_or(entry.name.as_str(), |info| info.title));
+        let author = info.and_then(|info| info.author).unwrap_or("Unknown author");
+        let credit = match info.and_then(|info| info.year) {
+            Some(year) => format!("{} {}", author, year),
+            None => author.to_string(),
+        };
+        draw_text(canvas, x, details + LINE_HEIGHT, &credit);
+    }
+    if !message.is_empty() {
+        draw_text(canvas, x, details + 2 * LINE_HEIGHT, message);
+    }
+    draw_text(canvas, x, details + 4 * LINE_HEIGHT, "Up/Down select  Enter start  Esc quit");
+    canvas.present();
+}
@@ -1,125 +1,422 @@
+mod analysis;
+mod config;
+mod coverage;
 mod cpu;
+mod debugger;
+mod disasm;
+mod display;
+mod filter;
+mod font;
+mod gamepad;
+mod gdb;
+mod history;
+mod headless;
+mod keypad;
+mod launcher;
+mod pacer;
+mod overlay;
+mod palette;
+mod profiler;
+mod recorder;
+mod romdb;
+mod runner;
+mod screenshot;
+mod terminal;
+mod text;
 mod timing;
+mod vip;
+mod watch;
 
 use std::env;
-use sdl3::event::Event;
-use sdl3::keyboard::Keycode;
+use sdl3::event::{Event, WindowEvent};
+use sdl3::keyboard::{Keycode, Mod, Scancode};
 
-use sdl3::pixels::Color;
+use sdl3::pixels::{Color, PixelFormat};
 use sdl3::rect::Rect;
-use sdl3::render::Canvas;
-use sdl3::video::Window;
-use std::fs::File;
-use std::io::Read;
-use std::time::{Duration, Instant};
-use crate::cpu::Chip8;
-use crate::timing::{TimedSystem,Timing};
+use sdl3::render::{Canvas, FRect, ScaleMode, Texture};
+use sdl3::video::{FullscreenType, Window};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use crate::config::{Config, ConfigFile, Frontend};
+use crate::coverage::Coverage;
+use crate::cpu::{Chip8, MAX_ROM_SIZE};
+use crate::debugger::{DebugCommand, Debugger};
+use crate::display::Stretch;
+use crate::filter::{DisplayFilter, FilterMode, Frame};
+use crate::gamepad::Gamepads;
+use crate::keypad::{Binding, HeldKeys, Keymap};
+use crate::pacer::{FramePacer, FrameStats};
+use crate::overlay::{Messages, Overlay};
+use crate::palette::Palette;
+use crate::profiler::Profiler;
+use crate::recorder::{RecordFormat, Recorder};
+use crate::runner::FRAMES_PER_SECOND;
+use crate::watch::RomWatcher;
 
 const WIDTH: usize = 128;
 const HEIGHT: usize = 64;
 
-const SCALE: u32 = 15;
-const WINDOW_WIDTH: u32 = (WIDTH as u32) * SCALE;
-const WINDOW_HEIGHT: u32 = (HEIGHT as u32) * SCALE;
+const DEFAULT_SCALE: u32 = 8;
 const TICKS_PER_FRAME: usize = 20;
 
 const LOWRES_WIDTH: usize = 64;
 const LOWRES_HEIGHT: usize = 32;
 
-const CPU_SYSTEM: &str = "cpu";
-const TIMER_SYSTEM: &str = "timer";
-const DISPLAY_SYSTEM: &str = "display";
-
 fn main() {
     let mut chip: Chip8 = Chip8::new();
 
     let args: Vec<_> = env::args().collect();
-    if args.len() != 3 {
-        println!("Usage: cargo run path/to/game chiptype");
-        return;
-    }
+    let mut config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(usage) => {
+            println!("{}", usage);
+            return;
+        }
+    };
 
-    let mut program = File::open(&args[1]).expect("Unable to open file");
-    let mut buffer = Vec::new();
+    let mut config_file = ConfigFile::load(config.config_path.clone());
+    let mut palette = config.palette.clone()
+        .or_else(|| config_file.get::<String>("palette").and_then(|spec| Palette::parse(&spec).ok()))
+        .unwrap_or_default();
 
+    if config.browse_dir.is_some() && config.frontend != Frontend::Sdl {
+        eprintln!("The ROM launcher needs the SDL frontend, pass a ROM path instead");
+        return;
+    }
+    if config.gdb_port.is_some() && config.frontend != Frontend::Headless {
+        eprintln!("The GDB server runs with the headless frontend, add --frontend headless");
+        return;
+    }
 
-    program.read_to_end(&mut buffer).unwrap();
+    if config.cfg_path.is_some() || config.disassemble {
+        analyze_rom(&config);
+        return;
+    }
 
-    chip.load_rom(&buffer);
-    chip.quirks.get_chip(&args[2]);
+    if config.profile || config.profile_json.is_some() {
+        chip.profiler = Some(Box::new(Profiler::new()));
+    }
+    if config.coverage_path.is_some() {
+        chip.coverage = Some(Box::new(Coverage::new()));
+    }
 
-    let mut timing = Timing::new(
-        Instant::now(),
-        vec![
-            TimedSystem::new(CPU_SYSTEM, 700),
-            TimedSystem::new(TIMER_SYSTEM, 60),
-            TimedSystem::new(DISPLAY_SYSTEM, 60),
-        ],
-    );
+    match config.frontend {
+        Frontend::Sdl => {},
+        Frontend::Terminal => {
+            if let Err(e) = start_game(&mut chip, &config) {
+                eprintln!("{}", e);
+                return;
+            }
+            let keymap = load_keymap(&config_file, &config);
+            let mut timing = runner::new_timing(&chip, config.catch_up);
+            if let Err(e) = terminal::run(&mut chip, &mut timing, config.glyphs, &keymap, config.frames) {
+                eprintln!("Terminal error: {}", e);
+            }
+            if let Some(error) = chip.take_error() {
+                eprintln!("{}", error);
+            }
+            report_profile(&chip, &config);
+            save_coverage(&chip, &config);
+            return;
+        },
+        Frontend::Headless => {
+            if let Err(e) = start_game(&mut chip, &config) {
+                eprintln!("{}", e);
+                return;
+            }
+            if let Some(port) = config.gdb_port {
+                if let Err(e) = gdb::serve(&mut chip, port) {
+                    eprintln!("GDB server error: {}", e);
+                }
+                return;
+            }
+            let mut console = Messages::Console;
+            let mut recorder = config.record_path
+                .as_deref()
+                .and_then(|path| start_recording(path, RecordFormat::from_path(path), &config, &palette, &mut console));
+            let frames = headless::run(&mut chip, config.frames, config.until_key_wait, |chip| {
+                capture_frame(&mut recorder, chip, &mut console)
+            });
+            stop_recording(&mut recorder, &mut console);
+            if let Some(error) = chip.take_error() {
+                console.error(error);
+            }
+            if chip.blocked_on_key() {
+                println!("Waiting for a key at {:#05X} after {} frames", chip.pc(), frames);
+            }
+            if config.screenshot {
+                save_screenshot(&chip, &config, &palette, &mut console);
+            }
+            report_profile(&chip, &config);
+            save_coverage(&chip, &config);
+            return;
+        },
+    }
 
     let sdl_context = sdl3::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
-    let window = video_subsystem
-        .window("Chip8 Emu", WINDOW_WIDTH, WINDOW_HEIGHT)
-        .position_centered()
-        .vulkan()
-        .build()
-        .unwrap();
+    // An explicit --scale wins over the geometry saved from the last run
+    let (window_width, window_height) = match config.scale {
+        Some(scale) => (WIDTH as u32 * scale, HEIGHT as u32 * scale),
+        None => (
+            config_file.get("window_width").unwrap_or(WIDTH as u32 * DEFAULT_SCALE),
+            config_file.get("window_height").unwrap_or(HEIGHT as u32 * DEFAULT_SCALE),
+        ),
+    };
+
+    let mut window_builder = video_subsystem.window("Chip8 Emu", window_width, window_height);
+    window_builder.resizable().vulkan();
+    match (config_file.get("window_x"), config_file.get("window_y")) {
+        (Some(x), Some(y)) if config.scale.is_none() => window_builder.position(x, y),
+        _ => window_builder.position_centered(),
+    };
+    let window = window_builder.build().unwrap();
 
-    let mut canvas = sdl3::render::create_renderer(window, None).unwrap();
+    let mut canvas = sdl3::render::create_renderer(window, Some(c"software")).unwrap();
+    // Let present() block on the display refresh when the driver supports it
+    // SAFETY: the renderer pointer comes from a live canvas that outlives the
+    // call, and SDL only reads it to change the renderer's vsync setting
+    let vsync = unsafe { sdl3::sys::render::SDL_SetRenderVSync(canvas.raw(), 1) };
+    let mut pacer = FramePacer::new(FRAMES_PER_SECOND, vsync);
+
+    let texture_creator = canvas.texture_creator();
+    let mut screen_texture = texture_creator
+        .create_texture_streaming(PixelFormat::ARGB8888, WIDTH as u32, HEIGHT as u32)
+        .unwrap();
+    screen_texture.set_scale_mode(ScaleMode::Nearest);
 
     canvas.clear();
     canvas.present();
     let mut event_pump = sdl_context.event_pump().unwrap();
 
+    if let Some(directory) = config.browse_dir.clone() {
+        let started = launcher::run(&mut canvas, &mut event_pump, &directory, |path| {
+            config.rom_path = path.to_string_lossy().into_owned();
+            start_game(&mut chip, &config)
+        });
+        if !started {
+            save_window_geometry(canvas.window(), &mut config_file);
+            return;
+        }
+    } else if let Err(e) = start_game(&mut chip, &config) {
+        eprintln!("{}", e);
+        return;
+    }
+    let mut overlay = Overlay::new();
+    set_title(canvas.window_mut(), &config, &mut overlay);
+
+    let mut keymap = load_keymap(&config_file, &config);
+    // Playing without gamepads is fine if the subsystem isn't available
+    let mut gamepads = match sdl_context.gamepad() {
+        Ok(subsystem) => {
+            Some(Gamepads::new(subsystem, &config_file, &config.rom_name(), &mut Messages::Overlay(&mut overlay)))
+        },
+        Err(e) => {
+            overlay.error(format!("Gamepads unavailable: {}", e));
+            None
+        },
+    };
+    let mut timing = runner::new_timing(&chip, config.catch_up);
+    let mut filter = DisplayFilter::new(config.filter);
+    chip.keep_cleared_screens(config.filter == FilterMode::Buffered);
+    let mut paused = false;
+    let mut debugger: Option<Debugger> = None;
+    let mut held_keys = HeldKeys::new();
+    let mut watcher = config.watch.then(|| RomWatcher::new(PathBuf::from(&config.rom_path)));
+    let mut recorder = config.record_path
+        .as_deref()
+        .and_then(|path| start_recording(path, RecordFormat::from_path(path), &config, &palette, &mut Messages::Overlay(&mut overlay)));
+
     'running: loop {
         for event in event_pump.poll_iter() {
+            // The debugger window gets its own keys, closing it only closes
+            // the debugger
+            if let Some(window) = debugger.as_mut().filter(|window| event.get_window_id() == Some(window.window_id())) {
+                if let Event::Window { win_event: WindowEvent::CloseRequested, .. } = event {
+                    if let Some(window) = debugger.take() {
+                        window.close(&mut chip);
+                    }
+                    continue;
+                }
+                match window.handle_event(&event, &mut chip) {
+                    Some(DebugCommand::Step) => {
+                        paused = true;
+                        chip.step();
+                    },
+                    Some(DebugCommand::Continue) => {
+                        paused = false;
+                        chip.resume();
+                    },
+                    Some(DebugCommand::Pause) => paused = true,
+                    Some(DebugCommand::StepBack) => {
+                        paused = true;
+                        if !window.history.step_back(&mut chip) {
+                            overlay.info("Start of history reached");
+                        }
+                    },
+                    Some(DebugCommand::ReverseContinue) => {
+                        paused = true;
+                        if window.history.reverse_continue(&mut chip) {
+                            overlay.info(format!("Breakpoint at {:#05X}", chip.pc()));
+                        } else {
+                            overlay.info("Start of history reached");
+                        }
+                    },
+                    None => {},
+                }
+                continue;
+            }
+            if let Some(gamepads) = &mut gamepads {
+                for (k, pressed) in gamepads.handle_event(&event, &mut Messages::Overlay(&mut overlay)) {
+                    chip.keypress(k, held_keys.update(k, pressed));
+                }
+            }
             match event {
                 Event::Quit { .. }
+                | Event::Window { win_event: WindowEvent::CloseRequested, .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => {
                     break 'running;
                 },
-                Event::KeyDown{keycode: Some(key), ..} => {
-                    if let Some(k) = button_translate(key) {
-                        chip.keypress(k, true);
+                Event::KeyDown{keycode: Some(Keycode::F5), ..} => {
+                    paused = !paused;
+                    if !paused {
+                        chip.resume();
                     }
                 },
-                Event::KeyUp{keycode: Some(key), ..} => {
-                    if let Some(k) = button_translate(key) {
-                        chip.keypress(k, false);
+                Event::KeyDown{keycode: Some(Keycode::F11), ..} => {
+                    toggle_fullscreen(canvas.window_mut(), &mut overlay);
+                },
+                Event::KeyDown{keycode: Some(Keycode::Return), keymod, ..}
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    toggle_fullscreen(canvas.window_mut(), &mut overlay);
+                },
+                Event::KeyDown{keycode: Some(Keycode::F8), ..} => {
+                    palette = palette.next();
+                    overlay.info(format!("Palette {}", palette.name));
+                    upload_screen(filter.output(), &mut screen_texture, &palette);
+                },
+                Event::KeyDown{keycode: Some(Keycode::F12), ..} => {
+                    save_screenshot(&chip, &config, &palette, &mut Messages::Overlay(&mut overlay));
+                },
+                Event::KeyDown{keycode: Some(Keycode::F9), ..} => {
+                    if recorder.is_some() {
+                        stop_recording(&mut recorder, &mut Messages::Overlay(&mut overlay));
+                    } else {
+                        let format = config.record_format;
+                        let path = screenshot::next_path(&config.rom_path, format.extension());
+                        recorder = start_recording(&path, format, &config, &palette, &mut Messages::Overlay(&mut overlay));
                     }
                 },
-                _ => {}
-            }
-        }
-
-        // The rest of the game loop goes here...
-        let instructions = timing.get_instructions(Instant::now());
-        for instruction in instructions {
-            match instruction.name {
-                CPU_SYSTEM => {
-                    for _ in 0..instruction.cycles {
-                        chip.clock();
+                Event::KeyDown{keycode: Some(Keycode::F3), ..} => {
+                    overlay.show_stats = !overlay.show_stats;
+                },
+                Event::KeyDown{keycode: Some(Keycode::F10), ..} if paused => {
+                    chip.step();
+                },
+                Event::KeyDown{keycode: Some(Keycode::F6), ..} => {
+                    chip.reset();
+                    overlay.info("Reset");
+                },
+                Event::KeyDown{keycode: Some(Keycode::F7), ..} => {
+                    reload_rom(&mut chip, &config.rom_path, &mut Messages::Overlay(&mut overlay));
+                },
+                Event::DropFile{filename, ..} => {
+                    // A dropped ROM gets its platform detected like one from
+                    // the launcher, the old one stays if it can't be loaded
+                    let previous = config.clone();
+                    config.rom_path = filename;
+                    config.chip_type = None;
+                    match start_game(&mut chip, &config) {
+                        Ok(()) => {
+                            held_keys = HeldKeys::new();
+                            set_title(canvas.window_mut(), &config, &mut overlay);
+                            keymap = load_keymap(&config_file, &config);
+                            if let Some(gamepads) = &mut gamepads {
+                                gamepads.load_map(&config_file, &config.rom_name(), &mut Messages::Overlay(&mut overlay));
+                            }
+                            if watcher.is_some() {
+                                watcher = Some(RomWatcher::new(PathBuf::from(&config.rom_path)));
+                            }
+                        },
+                        Err(e) => {
+                            overlay.error(e);
+                            config = previous;
+                        },
                     }
                 },
-                TIMER_SYSTEM => {
-                    for _ in 0..instruction.cycles {
-                        chip.update_timer();
+                Event::KeyDown{keycode: Some(Keycode::F2), ..} => {
+                    overlay.show_keypad = !overlay.show_keypad;
+                },
+                Event::KeyDown{keycode: Some(Keycode::F1), ..} => {
+                    overlay.show_info = !overlay.show_info;
+                },
+                Event::KeyDown{keycode: Some(Keycode::F4), ..} => {
+                    if let Some(window) = debugger.take() {
+                        window.close(&mut chip);
+                    } else {
+                        match Debugger::open(&video_subsystem, &mut chip) {
+                            Ok(window) => debugger = Some(window),
+                            Err(e) => overlay.error(format!("Unable to open the debugger: {}", e)),
+                        }
                     }
                 },
-                DISPLAY_SYSTEM => {
-                    for _ in 0..instruction.cycles {
-                        update_screen(&chip,&mut canvas);
+                Event::KeyDown{keycode, scancode, repeat: false, ..} => {
+                    if let Some(k) = button_translate(&keymap, keycode, scancode) {
+                        chip.keypress(k, held_keys.update(k, true));
                     }
                 },
-                unknown => panic!("Unexpected instruction {}", unknown),
+                Event::KeyUp{keycode, scancode, ..} => {
+                    if let Some(k) = button_translate(&keymap, keycode, scancode) {
+                        chip.keypress(k, held_keys.update(k, false));
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        if watcher.as_mut().is_some_and(RomWatcher::changed) {
+            reload_rom(&mut chip, &config.rom_path, &mut Messages::Overlay(&mut overlay));
+        }
+
+        // The rest of the game loop goes here...
+        let instructions = timing.get_instructions(Instant::now());
+        let instructions_before = chip.instruction_count();
+        let frames_due = runner::execute_instructions(&mut chip, instructions, paused);
+        pacer.instructions_executed(chip.instruction_count() - instructions_before);
+        if let Some(window) = &mut debugger {
+            window.history.record(&mut chip);
+        }
+        for _ in 0..frames_due {
+            capture_frame(&mut recorder, &chip, &mut Messages::Overlay(&mut overlay));
+        }
+
+        // Only the latest frame is shown, anything else that was due is dropped
+        pacer.frame_presented(frames_due.saturating_sub(1));
+        let stats = if overlay.show_stats { Some(pacer.stats()) } else { None };
+        if let Some(error) = chip.take_error() {
+            overlay.error(error);
+        }
+        if chip.take_breakpoint_hit() {
+            paused = true;
+            overlay.info(format!("Breakpoint at {:#05X}", chip.pc()));
+        }
+        let dirty = chip.take_screen_dirty();
+        if dirty || frames_due > 0 {
+            let cleared = if frames_due > 0 { chip.take_cleared_screen() } else { None };
+            if filter.update(chip.get_screen_buf(), cleared.as_deref(), dirty, frames_due) {
+                upload_screen(filter.output(), &mut screen_texture, &palette);
             }
         }
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60)); // 60fps
+        update_screen(&chip, &mut canvas, &screen_texture, config.stretch, &mut overlay, stats, paused);
+        if let Some(debugger) = &mut debugger {
+            debugger.draw(&chip, paused);
+        }
+        pacer.wait();
 
         // for _ in 0..TICKS_PER_FRAME {
         //     chip.clock();
@@ -127,48 +424,262 @@ fn main() {
         // chip.update_timer();
         // update_screen(&chip, &mut canvas);
     }
+
+    // The window is gone, so this is printed
+    stop_recording(&mut recorder, &mut Messages::Console);
+    report_profile(&chip, &config);
+    save_coverage(&chip, &config);
+    save_window_geometry(canvas.window(), &mut config_file);
+}
+
+fn save_screenshot(chip: &Chip8, config: &Config, palette: &Palette, messages: &mut Messages) {
+    let path = screenshot::next_path(&config.rom_path, "png");
+    match screenshot::save_png(&path, chip, palette, config.screenshot_scale) {
+        Ok(()) => messages.info(format!("Screenshot saved to {}", path.display())),
+        Err(e) => messages.error(format!("Unable to save screenshot {}: {}", path.display(), e)),
+    }
 }
 
-fn update_screen(emu: &Chip8, canvas: &mut Canvas<Window>) {
-    canvas.set_draw_color(Color::RGBA(0, 0, 0,255));
-    canvas.clear();
-    let _width = if emu.get_hires() { WIDTH } else { LOWRES_WIDTH };
-
-    let screen_buf = emu.get_screen_buf();
-    canvas.set_draw_color(Color::RGBA(255, 255, 255,255));
-    for (i, col) in screen_buf.iter().enumerate() {
-        for(j,pixel) in col.iter().enumerate() {
-            if *pixel {
-                let x = j as u32;
-                let y = i as u32;
-
-                let rect = Rect::new((x * SCALE) as i32, (y * SCALE) as i32, SCALE, SCALE);
-                canvas.fill_rect(rect).unwrap();
-            }
+// Prints the profile, or saves it when a JSON file was asked for
+fn report_profile(chip: &Chip8, config: &Config) {
+    let Some(profiler) = &chip.profiler else {
+        return;
+    };
+    match &config.profile_json {
+        Some(path) => match profiler.save_json(path, chip.memory()) {
+            Ok(()) => println!("Profile saved to {}", path.display()),
+            Err(e) => eprintln!("Unable to save profile {}: {}", path.display(), e),
+        },
+        None => print!("{}", profiler.report(chip.memory())),
+    }
+}
+
+// Walks the ROM without running it, printing the listing and what the
+// walk found and writing the control flow graph
+fn analyze_rom(config: &Config) {
+    let rom = match fs::read(&config.rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Unable to open {}: {}", config.rom_path, e);
+            return;
+        },
+    };
+    if rom.len() > MAX_ROM_SIZE {
+        eprintln!("ROM is {} bytes, only {} fit in memory", rom.len(), MAX_ROM_SIZE);
+        return;
+    }
+    let analysis = analysis::analyze(&rom);
+    if config.disassemble {
+        print!("{}", analysis.listing());
+    }
+    if let Some(path) = &config.cfg_path {
+        match fs::write(path, analysis.to_dot()) {
+            Ok(()) => println!(
+                "Control flow graph with {} blocks and {} subroutines saved to {}",
+                analysis.blocks.len(),
+                analysis.calls.len() - 1,
+                path.display(),
+            ),
+            Err(e) => eprintln!("Unable to save {}: {}", path.display(), e),
         }
+    }
+    for finding in analysis.describe_findings() {
+        println!("{}", finding);
+    }
+}
+
+// Writes the annotated disassembly and the heatmap as a .png beside it
+fn save_coverage(chip: &Chip8, config: &Config) {
+    let (Some(coverage), Some(path)) = (&chip.coverage, &config.coverage_path) else {
+        return;
+    };
+    let heatmap = heatmap_path(path);
+    let analysis = analysis::analyze(chip.rom());
+    let result = coverage
+        .save_text(path, chip.rom(), &analysis)
+        .and_then(|()| coverage.save_png(&heatmap, chip.rom().len()));
+    match result {
+        Ok(()) => println!("Coverage saved to {} and {}", path.display(), heatmap.display()),
+        Err(e) => eprintln!("Unable to save coverage {}: {}", path.display(), e),
+    }
+}
+
+// `map.txt` gets `map.png`, and a coverage path that is a .png itself gets
+// `map-heatmap.png` so the text isn't overwritten
+fn heatmap_path(path: &Path) -> PathBuf {
+    let heatmap = path.with_extension("png");
+    if heatmap != path {
+        return heatmap;
+    }
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!("{}-heatmap.png", stem))
+}
+
+// Loads the ROM from `config.rom_path` and sets the machine up for its
+// platform. The running game is left alone when the ROM can't be read or
+// doesn't fit.
+fn start_game(chip: &mut Chip8, config: &Config) -> Result<(), String> {
+    let rom = fs::read(&config.rom_path).map_err(|e| format!("Unable to open {}: {}", config.rom_path, e))?;
+
+    let platform = config.chip_type
+        .clone()
+        .unwrap_or_else(|| romdb::detect_platform(Path::new(&config.rom_path), &rom).to_string());
+    chip.load_rom(&rom).map_err(|e| format!("Unable to load {}: {}", config.rom_path, e))?;
+    chip.quirks.get_chip(&platform);
+    chip.timing_mode = config.timing_mode;
+    if let Some(display_wait) = config.display_wait {
+        chip.quirks.display_wait = display_wait;
+    }
+    if let Some(key_wait) = config.key_wait {
+        chip.quirks.key_wait = key_wait;
+    }
+    Ok(())
+}
+
+fn set_title(window: &mut Window, config: &Config, overlay: &mut Overlay) {
+    let title = format!("Chip8 Emu - {}", config.rom_name());
+    if let Err(e) = window.set_title(&title) {
+        overlay.error(format!("Unable to set the window title: {}", e));
+    }
+}
+
+fn load_keymap(config_file: &ConfigFile, config: &Config) -> Keymap {
+    let mut keymap = Keymap::load(config_file, &config.rom_name());
+    if let Some(binding) = config.key_binding {
+        keymap.binding = binding;
+    }
+    keymap
+}
 
+// Hard reset: reads the ROM from disk again, keeping the old program if
+// that fails
+fn reload_rom(chip: &mut Chip8, rom_path: &str, messages: &mut Messages) {
+    match fs::read(rom_path) {
+        Ok(rom) => match chip.load_rom(&rom) {
+            Ok(()) => messages.info(format!("Reloaded {}", rom_path)),
+            Err(e) => messages.error(format!("Unable to reload {}: {}", rom_path, e)),
+        },
+        Err(e) => messages.error(format!("Unable to reload {}: {}", rom_path, e)),
     }
+}
+
+fn start_recording(
+    path: &Path,
+    format: RecordFormat,
+    config: &Config,
+    palette: &Palette,
+    messages: &mut Messages,
+) -> Option<Recorder> {
+    let audio_path = config.record_audio.then(|| path.with_extension("wav"));
+    match Recorder::start(path, format, audio_path.as_deref(), palette, config.screenshot_scale) {
+        Ok(recorder) => {
+            messages.info(format!("Recording to {}", path.display()));
+            Some(recorder)
+        },
+        Err(e) => {
+            messages.error(format!("Unable to record to {}: {}", path.display(), e));
+            None
+        },
+    }
+}
+
+// A failed write ends the recording instead of failing every frame after it
+fn capture_frame(recorder: &mut Option<Recorder>, chip: &Chip8, messages: &mut Messages) {
+    if let Some(active) = recorder {
+        if let Err(e) = active.capture(chip) {
+            messages.error(format!("Recording failed: {}", e));
+            *recorder = None;
+        }
+    }
+}
+
+fn stop_recording(recorder: &mut Option<Recorder>, messages: &mut Messages) {
+    if let Some(active) = recorder.take() {
+        match active.finish() {
+            Ok(()) => messages.info("Recording stopped"),
+            Err(e) => messages.error(format!("Unable to finish recording: {}", e)),
+        }
+    }
+}
+
+fn toggle_fullscreen(window: &mut Window, overlay: &mut Overlay) {
+    let fullscreen = window.fullscreen_state() != FullscreenType::Off;
+    if let Err(e) = window.set_fullscreen(!fullscreen) {
+        overlay.error(format!("Unable to change fullscreen mode: {}", e));
+    }
+}
+
+fn save_window_geometry(window: &Window, config_file: &mut ConfigFile) {
+    // Keep the windowed geometry from before going fullscreen
+    if window.fullscreen_state() == FullscreenType::Off {
+        let (x, y) = window.position();
+        let (width, height) = window.size();
+        config_file.set("window_x", x);
+        config_file.set("window_y", y);
+        config_file.set("window_width", width);
+        config_file.set("window_height", height);
+    }
+
+    if let Err(e) = config_file.save() {
+        eprintln!("Unable to save settings: {}", e);
+    }
+}
+
+// Copies the filtered frame into the streaming texture, one ARGB8888 pixel
+// per CHIP-8 pixel
+fn upload_screen(frame: &Frame, texture: &mut Texture, palette: &Palette) {
+    texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+        for (y, row) in frame.iter().enumerate() {
+            for (x, brightness) in row.iter().enumerate() {
+                let color = 0xFF00_0000 | blend(palette.color(0), palette.color(1), *brightness);
+                let offset = y * pitch + x * 4;
+                buffer[offset..offset + 4].copy_from_slice(&color.to_ne_bytes());
+            }
+        }
+    }).unwrap();
+}
+
+// Mixes two 0xRRGGBB colors, `amount` 0 is all `from` and 255 all `to`
+fn blend(from: u32, to: u32, amount: u8) -> u32 {
+    let amount = u32::from(amount);
+    [16, 8, 0].iter().fold(0, |color, shift| {
+        let from = (from >> shift) & 0xFF;
+        let to = (to >> shift) & 0xFF;
+        color | (((from * (255 - amount) + to * amount) / 255) << shift)
+    })
+}
+
+fn update_screen(
+    emu: &Chip8,
+    canvas: &mut Canvas<Window>,
+    texture: &Texture,
+    stretch: Stretch,
+    overlay: &mut Overlay,
+    stats: Option<FrameStats>,
+    paused: bool,
+) {
+    canvas.set_draw_color(Color::RGBA(0, 0, 0,255));
+    canvas.clear();
+
+    // Lores games only use the top-left quarter of the framebuffer, which is
+    // scaled up to fill the same area as a hires screen
+    let (width, height) = if emu.get_hires() { (WIDTH, HEIGHT) } else { (LOWRES_WIDTH, LOWRES_HEIGHT) };
+    let source = Rect::new(0, 0, width as u32, height as u32);
+    let destination = display::destination_rect(
+        canvas.output_size().unwrap(),
+        (width as u32, height as u32),
+        stretch,
+    );
+    canvas.copy(texture, FRect::from(source), FRect::from(destination)).unwrap();
+
+    overlay.draw(canvas, emu, stats, paused);
     canvas.present();
 }
 
-fn button_translate(key: Keycode) -> Option<usize> {
-    match key {
-        Keycode::_1 =>    Some(0x1),
-        Keycode::_2 =>    Some(0x2),
-        Keycode::_3 =>    Some(0x3),
-        Keycode::_4 =>    Some(0xC),
-        Keycode::Q =>       Some(0x4),
-        Keycode::W =>       Some(0x5),
-        Keycode::E =>       Some(0x6),
-        Keycode::R =>       Some(0xD),
-        Keycode::A =>       Some(0x7),
-        Keycode::S =>       Some(0x8),
-        Keycode::D =>       Some(0x9),
-        Keycode::F =>       Some(0xE),
-        Keycode::Z =>       Some(0xA),
-        Keycode::X =>       Some(0x0),
-        Keycode::C =>       Some(0xB),
-        Keycode::V =>       Some(0xF),
-        _ =>                None,
-    }
-}
\ No newline at end of file
+fn button_translate(keymap: &Keymap, keycode: Option<Keycode>, scancode: Option<Scancode>) -> Option<usize> {
+    let name = match keymap.binding {
+        Binding::Scancode => scancode?.name().to_string(),
+        Binding::Keycode => keycode?.name(),
+    };
+    keymap.translate(&name)
+}
@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use sdl3::pixels::Color;
+use sdl3::render::Canvas;
+use sdl3::video::Window;
+use crate::cpu::Chip8;
+use crate::font;
+use crate::keypad;
+use crate::pacer::FrameStats;
+use crate::text::{draw_label, draw_text, LINE_HEIGHT, OVERLAY_SCALE};
+
+// How long a message stays on screen
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+// Older messages are dropped when more than this many are showing
+const MAX_MESSAGES: usize = 4;
+
+const TEXT_COLOR: Color = Color::RGBA(255, 255, 0, 255);
+const ERROR_COLOR: Color = Color::RGBA(255, 64, 64, 255);
+const BACKGROUND: Color = Color::RGBA(0, 0, 0, 255);
+
+struct Message {
+    text: String,
+    error: bool,
+    shown: Instant,
+}
+
+// Where status messages go: the overlay when there is a window, stdout and
+// stderr when there isn't
+pub(crate) enum Messages<'a> {
+    Overlay(&'a mut Overlay),
+    Console,
+}
+
+impl Messages<'_> {
+    pub(crate) fn info(&mut self, text: impl Into<String>) {
+        match self {
+            Messages::Overlay(overlay) => overlay.info(text),
+            Messages::Console => println!("{}", text.into()),
+        }
+    }
+
+    pub(crate) fn error(&mut self, text: impl Into<String>) {
+        match self {
+            Messages::Overlay(overlay) => overlay.error(text),
+            Messages::Console => eprintln!("{}", text.into()),
+        }
+    }
+}
+
+// Status drawn on top of the emulated screen: transient messages, frame
+// statistics, the pause indicator, platform info and the keypad
+pub(crate) struct Overlay {
+    messages: VecDeque<Message>,
+    pub(crate) show_stats: bool,
+    pub(crate) show_info: bool,
+    pub(crate) show_keypad: bool,
+}
+
+impl Overlay {
+    pub(crate) fn new() -> Self {
+        Self {
+            messages: VecDeque::new(),
+            show_stats: false,
+            show_info: false,
+            show_keypad: false,
+        }
+    }
+
+    pub(crate) fn info(&mut self, text: impl Into<String>) {
+        self.push(text.into(), false);
+    }
+
+    pub(crate) fn error(&mut self, text: impl Into<String>) {
+        self.push(text.into(), true);
+    }
+
+    // A message repeating the newest one only keeps it up longer, so an
+    // error hit every frame doesn't fill the screen
+    fn push(&mut self, text: String, error: bool) {
+        if let Some(last) = self.messages.back_mut() {
+            if last.text == text {
+                last.shown = Instant::now();
+                return;
+            }
+        }
+        self.messages.push_back(Message { text, error, shown: Instant::now() });
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+    }
+
+    pub(crate) fn draw(&mut self, canvas: &mut Canvas<Window>, emu: &Chip8, stats: Option<FrameStats>, paused: bool) {
+        let (output_width, output_height) = canvas.output_size().unwrap();
+        let mut y = OVERLAY_SCALE;
+
+        if let Some(stats) = stats {
+            let mut text = format!("FPS {:.0}  IPS {:.0}  DROPPED {}", stats.fps, stats.ips, stats.dropped_frames);
+            if emu.blocked_on_key() {
+                text.push_str("  KEY WAIT");
+            }
+            draw_text(canvas, OVERLAY_SCALE, y, &text);
+            y += LINE_HEIGHT;
+        }
+        if self.show_info {
+            let platform = format!("{}  PC {:#05X}", emu.quirks.platform(), emu.pc());
+            draw_text(canvas, OVERLAY_SCALE, y, &platform);
+            draw_text(canvas, OVERLAY_SCALE, y + LINE_HEIGHT, &emu.quirks.describe());
+        }
+
+        if paused {
+            let width = (font::text_width("PAUSED") + 2) * OVERLAY_SCALE;
+            draw_text(canvas, output_width.saturating_sub(width + OVERLAY_SCALE), OVERLAY_SCALE, "PAUSED");
+        }
+
+        self.messages.retain(|message| message.shown.elapsed() < MESSAGE_DURATION);
+        let bottom = output_height.saturating_sub(OVERLAY_SCALE);
+        let count = self.messages.len() as u32;
+        for (index, message) in self.messages.iter().enumerate() {
+            let y = bottom.saturating_sub((count - index as u32) * LINE_HEIGHT);
+            let color = if message.error { ERROR_COLOR } else { TEXT_COLOR };
+            draw_label(canvas, OVERLAY_SCALE, y, &message.text, BACKGROUND, color);
+        }
+
+        if self.show_keypad {
+            draw_keypad(canvas, emu);
+        }
+    }
+}
+
+// The hex keypad in the bottom right corner, pressed keys drawn inverted
+fn draw_keypad(canvas: &mut Canvas<Window>, emu: &Chip8) {
+    let cell_width = (font::GLYPH_WIDTH + 3) * OVERLAY_SCALE;
+    let cell_height = (font::GLYPH_HEIGHT + 3) * OVERLAY_SCALE;
+    let (output_width, output_height) = canvas.output_size().unwrap();
+    let left = output_width.saturating_sub(4 * cell_width + OVERLAY_SCALE);
+    let top = output_height.saturating_sub(4 * cell_height + OVERLAY_SCALE);
+
+    for (row, keys) in keypad::KEYPAD_ROWS.iter().enumerate() {
+        for (column, key) in keys.iter().enumerate() {
+            let (background, foreground) = if emu.is_key_pressed(*key) {
+                (TEXT_COLOR, BACKGROUND)
+            } else {
+                (BACKGROUND, TEXT_COLOR)
+            };
+            let x = left + column as u32 * cell_width;
+            let y = top + row as u32 * cell_height;
+            draw_label(canvas, x, y, &format!("{:X}", key), background, foreground);
+        }
+    }
+}
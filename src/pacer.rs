@@ -0,0 +1,90 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often the statistics are recomputed
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Default)]
+pub(crate) struct FrameStats {
+    pub(crate) fps: f64,
+    pub(crate) ips: f64,
+    pub(crate) dropped_frames: u64,
+}
+
+// Keeps the main loop running at the display rate. With vsync the renderer's
+// present already blocks until the next refresh, otherwise we only sleep for
+// whatever is left of the frame after the work that was done in it.
+pub(crate) struct FramePacer {
+    frame_duration: Duration,
+    vsync: bool,
+    next_frame: Instant,
+    stats: FrameStats,
+    stats_start: Instant,
+    stats_frames: u64,
+    stats_instructions: u64,
+}
+
+impl FramePacer {
+    pub(crate) fn new(frames_per_second: u32, vsync: bool) -> Self {
+        let now = Instant::now();
+        let frame_duration = Duration::from_secs(1) / frames_per_second;
+        Self {
+            frame_duration,
+            vsync,
+            next_frame: now + frame_duration,
+            stats: FrameStats::default(),
+            stats_start: now,
+            stats_frames: 0,
+            stats_instructions: 0,
+        }
+    }
+
+    // Records a presented frame, along with the frames that were due but
+    // never shown because we fell behind. This is the only place drops are
+    // counted.
+    pub(crate) fn frame_presented(&mut self, dropped: u64) {
+        self.stats_frames += 1;
+        self.stats.dropped_frames += dropped;
+    }
+
+    pub(crate) fn instructions_executed(&mut self, count: u64) {
+        self.stats_instructions += count;
+    }
+
+    pub(crate) fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    // Blocks until the next frame should start
+    pub(crate) fn wait(&mut self) {
+        let now = Instant::now();
+        self.update_stats(now);
+
+        if self.vsync {
+            return;
+        }
+
+        if now < self.next_frame {
+            thread::sleep(self.next_frame - now);
+            self.next_frame += self.frame_duration;
+        } else {
+            // We're late: don't try to make up for it by rushing frames out.
+            // The frames this skips show up as dropped in `frame_presented`.
+            self.next_frame = now + self.frame_duration;
+        }
+    }
+
+    fn update_stats(&mut self, now: Instant) {
+        let elapsed = now - self.stats_start;
+        if elapsed < STATS_INTERVAL {
+            return;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        self.stats.fps = self.stats_frames as f64 / seconds;
+        self.stats.ips = self.stats_instructions as f64 / seconds;
+        self.stats_start = now;
+        self.stats_frames = 0;
+        self.stats_instructions = 0;
+    }
+}
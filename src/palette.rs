@@ -0,0 +1,75 @@
+// Colors used to draw the screen. Index 0 is the background and index 1 the
+// lit pixels. Indices 2 and 3 are reserved for XO-CHIP's second plane and
+// the overlap of both planes: the core only has one plane, so nothing draws
+// with them yet.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub(crate) struct Palette {
+    pub(crate) name: String,
+    // 0xRRGGBB
+    pub(crate) colors: [u32; 4],
+}
+
+const PRESETS: [(&str, [u32; 4]); 6] = [
+    ("classic", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+    ("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+    ("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+    ("amber", [0x1A0F00, 0xFFB000, 0xCC7A00, 0x664000]),
+    ("cyberpunk", [0x0D0221, 0xFF2A6D, 0x05D9E8, 0xD1F7FF]),
+    ("contrast", [0x000000, 0xFFFFFF, 0xFFFF00, 0x00FFFF]),
+];
+
+impl Palette {
+    pub(crate) fn preset(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|(preset, colors)| Self { name: preset.to_string(), colors: *colors })
+    }
+
+    // Either the name of a preset or a comma separated list of two or four
+    // hex colors, e.g. "#000000,#FFFFFF". The last two of four are kept for
+    // the planes, see above.
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        if let Some(palette) = Self::preset(spec) {
+            return Ok(palette);
+        }
+
+        let parsed: Result<Vec<u32>, String> = spec.split(',').map(parse_color).collect();
+        let colors = match parsed?.as_slice() {
+            [background, foreground] => [*background, *foreground, *foreground, *foreground],
+            [background, plane1, plane2, both] => [*background, *plane1, *plane2, *both],
+            _ => return Err(format!("Palette {} needs a preset name, 2 or 4 colors", spec)),
+        };
+        Ok(Self { name: "custom".to_string(), colors })
+    }
+
+    // The preset after this one, wrapping around. Custom palettes go back to
+    // the first preset.
+    pub(crate) fn next(&self) -> Self {
+        let index = PRESETS
+            .iter()
+            .position(|(preset, _)| *preset == self.name)
+            .map_or(0, |index| (index + 1) % PRESETS.len());
+        let (name, colors) = PRESETS[index];
+        Self { name: name.to_string(), colors }
+    }
+
+    pub(crate) fn color(&self, index: u8) -> u32 {
+        self.colors[index as usize & 3]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let (name, colors) = PRESETS[0];
+        Self { name: name.to_string(), colors }
+    }
+}
+
+fn parse_color(text: &str) -> Result<u32, String> {
+    let hex = text.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return Err(format!("Color {} should look like #RRGGBB", text));
+    }
+    u32::from_str_radix(hex, 16).map_err(|_| format!("Color {} should look like #RRGGBB", text))
+}
@@ -0,0 +1,167 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::disasm;
+
+// Lines in each section of the text report
+const REPORT_LINES: usize = 20;
+
+#[derive(Clone, Copy, Default)]
+struct Subroutine {
+    calls: u64,
+    // Cycles spent between the calls and their returns
+    inclusive_cycles: u64,
+}
+
+// Counts where a ROM spends its time. Cycles are instructions with fixed
+// timing and machine cycles with COSMAC VIP timing.
+#[derive(Clone)]
+pub(crate) struct Profiler {
+    counts: Vec<u64>,
+    classes: BTreeMap<&'static str, u64>,
+    subroutines: BTreeMap<u16, Subroutine>,
+    // Subroutines entered and not returned from, with the cycle count at
+    // the call
+    calls: Vec<(u16, u64)>,
+    instructions: u64,
+    cycles: u64,
+}
+
+impl Profiler {
+    pub(crate) fn new() -> Self {
+        Self {
+            counts: vec![0; 4096],
+            classes: BTreeMap::new(),
+            subroutines: BTreeMap::new(),
+            calls: Vec::new(),
+            instructions: 0,
+            cycles: 0,
+        }
+    }
+
+    // Called after every instruction with its address and cost
+    pub(crate) fn record(&mut self, pc: u16, opcode: u16, cycles: u64) {
+        self.counts[pc as usize & 0xFFF] += 1;
+        *self.classes.entry(disasm::pattern(opcode)).or_default() += 1;
+        self.instructions += 1;
+        self.cycles += cycles;
+
+        if opcode & 0xF000 == 0x2000 {
+            let target = opcode & 0xFFF;
+            self.subroutines.entry(target).or_default().calls += 1;
+            self.calls.push((target, self.cycles));
+        } else if opcode == 0x00EE {
+            if let Some((target, start)) = self.calls.pop() {
+                self.subroutines.entry(target).or_default().inclusive_cycles += self.cycles - start;
+            }
+        }
+    }
+
+    // Subroutines with the cycles of calls still running counted up to now
+    fn finished_subroutines(&self) -> BTreeMap<u16, Subroutine> {
+        let mut subroutines = self.subroutines.clone();
+        for (target, start) in &self.calls {
+            subroutines.entry(*target).or_default().inclusive_cycles += self.cycles - start;
+        }
+        subroutines
+    }
+
+    // Addresses by how often they were executed, most first
+    fn hotspots(&self) -> Vec<(u16, u64)> {
+        let mut hotspots: Vec<(u16, u64)> = self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(address, count)| (address as u16, *count))
+            .collect();
+        hotspots.sort_by_key(|(address, count)| (Reverse(*count), *address));
+        hotspots
+    }
+
+    // The human readable report, instructions disassembled from `memory`
+    pub(crate) fn report(&self, memory: &[u8]) -> String {
+        let percent = |part: u64, total: u64| part as f64 * 100.0 / total.max(1) as f64;
+        let mut report = String::new();
+        let _ = writeln!(report, "Profile: {} instructions, {} cycles", self.instructions, self.cycles);
+
+        let _ = writeln!(report, "\nHotspots\n  address     count      %  instruction");
+        for (address, count) in self.hotspots().into_iter().take(REPORT_LINES) {
+            let _ = writeln!(
+                report,
+                "  {:#05X}  {:>10}  {:>5.1}  {}",
+                address,
+                count,
+                percent(count, self.instructions),
+                disasm::disassemble(opcode_at(memory, address)),
+            );
+        }
+
+        let _ = writeln!(report, "\nOpcodes\n  opcode      count      %");
+        let mut classes: Vec<_> = self.classes.iter().collect();
+        classes.sort_by_key(|(_, count)| Reverse(**count));
+        for (class, count) in classes {
+            let _ = writeln!(report, "  {:<6} {:>10}  {:>5.1}", class, count, percent(*count, self.instructions));
+        }
+
+        let _ = writeln!(report, "\nSubroutines\n  address     calls  inclusive cycles      %");
+        let mut subroutines: Vec<_> = self.finished_subroutines().into_iter().collect();
+        subroutines.sort_by_key(|(_, subroutine)| Reverse(subroutine.inclusive_cycles));
+        for (address, subroutine) in subroutines.into_iter().take(REPORT_LINES) {
+            let _ = writeln!(
+                report,
+                "  {:#05X}  {:>10}  {:>16}  {:>5.1}",
+                address,
+                subroutine.calls,
+                subroutine.inclusive_cycles,
+                percent(subroutine.inclusive_cycles, self.cycles),
+            );
+        }
+        report
+    }
+
+    // The whole profile as JSON, every executed address included
+    pub(crate) fn save_json(&self, path: &Path, memory: &[u8]) -> io::Result<()> {
+        let hotspots: Vec<String> = self.hotspots()
+            .into_iter()
+            .map(|(address, count)| {
+                format!(
+                    "    {{\"address\": {}, \"count\": {}, \"instruction\": \"{}\"}}",
+                    address,
+                    count,
+                    disasm::disassemble(opcode_at(memory, address)),
+                )
+            })
+            .collect();
+        let classes: Vec<String> = self.classes
+            .iter()
+            .map(|(class, count)| format!("    \"{}\": {}", class, count))
+            .collect();
+        let subroutines: Vec<String> = self.finished_subroutines()
+            .iter()
+            .map(|(address, subroutine)| {
+                format!(
+                    "    {{\"address\": {}, \"calls\": {}, \"inclusive_cycles\": {}}}",
+                    address, subroutine.calls, subroutine.inclusive_cycles,
+                )
+            })
+            .collect();
+
+        let json = format!(
+            "{{\n  \"instructions\": {},\n  \"cycles\": {},\n  \"hotspots\": [\n{}\n  ],\n  \"opcodes\": {{\n{}\n  }},\n  \"subroutines\": [\n{}\n  ]\n}}\n",
+            self.instructions,
+            self.cycles,
+            hotspots.join(",\n"),
+            classes.join(",\n"),
+            subroutines.join(",\n"),
+        );
+        fs::write(path, json)
+    }
+}
+
+fn opcode_at(memory: &[u8], address: u16) -> u16 {
+    let address = address as usize;
+    u16::from_be_bytes([memory[address], memory.get(address + 1).copied().unwrap_or(0)])
+}
@@ -0,0 +1,256 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use crate::cpu::{Chip8, HEIGHT, LOWRES_HEIGHT, LOWRES_WIDTH, WIDTH};
+use crate::palette::Palette;
+use crate::runner::FRAMES_PER_SECOND;
+
+const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / FRAMES_PER_SECOND;
+const BEEP_HZ: u32 = 440;
+const BEEP_AMPLITUDE: i16 = 8_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum RecordFormat {
+    Gif,
+    Y4m,
+}
+
+impl RecordFormat {
+    // .y4m for a raw video stream, anything else is an animated GIF
+    pub(crate) fn from_path(path: &Path) -> Self {
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("y4m")) {
+            RecordFormat::Y4m
+        } else {
+            RecordFormat::Gif
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Gif => "gif",
+            RecordFormat::Y4m => "y4m",
+        }
+    }
+}
+
+// Records every displayed frame to a file, along with the beeper when asked.
+// Recordings always have the hires size, lores frames are scaled up 2x.
+pub(crate) struct Recorder {
+    video: Video,
+    audio: Option<Wav>,
+    scale: usize,
+}
+
+enum Video {
+    // Identical consecutive frames are merged into one with a longer delay
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        pending: Option<(Vec<u8>, u64)>,
+        frames_written: u64,
+        centiseconds_written: u64,
+    },
+    // Uncompressed YUV 4:4:4 at a constant 60 frames per second
+    Y4m {
+        writer: BufWriter<File>,
+        palette: Palette,
+    },
+}
+
+impl Recorder {
+    pub(crate) fn start(
+        path: &Path,
+        format: RecordFormat,
+        audio_path: Option<&Path>,
+        palette: &Palette,
+        scale: u32,
+    ) -> io::Result<Self> {
+        let scale = scale.max(1) as usize;
+        let width = WIDTH * scale;
+        let height = HEIGHT * scale;
+        let writer = BufWriter::new(File::create(path)?);
+
+        let video = match format {
+            RecordFormat::Y4m => {
+                let mut writer = writer;
+                writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, FRAMES_PER_SECOND)?;
+                Video::Y4m { writer, palette: palette.clone() }
+            },
+            RecordFormat::Gif => {
+                let global_palette: Vec<u8> = palette.colors
+                    .iter()
+                    .flat_map(|color| [(color >> 16) as u8, (color >> 8) as u8, *color as u8])
+                    .collect();
+                let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &global_palette)
+                    .map_err(io::Error::other)?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+                Video::Gif { encoder, pending: None, frames_written: 0, centiseconds_written: 0 }
+            },
+        };
+
+        let audio = match audio_path {
+            Some(audio_path) => Some(Wav::create(audio_path)?),
+            None => None,
+        };
+
+        Ok(Self { video, audio, scale })
+    }
+
+    // Adds one display frame of the current machine state
+    pub(crate) fn capture(&mut self, chip: &Chip8) -> io::Result<()> {
+        let pixels = self.indexed_pixels(chip);
+        match &mut self.video {
+            Video::Gif { encoder, pending, frames_written, centiseconds_written } => match pending.take() {
+                Some((previous, count)) if previous == pixels => *pending = Some((previous, count + 1)),
+                // Too short for decoders to honour, the new frame replaces it
+                // and takes over its time
+                Some((_, count)) if gif_delay(*frames_written + count, *centiseconds_written) < MIN_GIF_DELAY => {
+                    *pending = Some((pixels, count + 1));
+                },
+                Some((previous, count)) => {
+                    write_gif_frame(encoder, self.scale, previous, count, frames_written, centiseconds_written)?;
+                    *pending = Some((pixels, 1));
+                },
+                None => *pending = Some((pixels, 1)),
+            },
+            Video::Y4m { writer, palette } => {
+                writer.write_all(b"FRAME\n")?;
+                let yuv: Vec<(u8, u8, u8)> = pixels.iter().map(|index| rgb_to_yuv(palette.color(*index))).collect();
+                writer.write_all(&yuv.iter().map(|(y, _, _)| *y).collect::<Vec<u8>>())?;
+                writer.write_all(&yuv.iter().map(|(_, u, _)| *u).collect::<Vec<u8>>())?;
+                writer.write_all(&yuv.iter().map(|(_, _, v)| *v).collect::<Vec<u8>>())?;
+            },
+        }
+
+        if let Some(audio) = &mut self.audio {
+            audio.write_frame(chip.sound_active())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self.video {
+            Video::Gif { mut encoder, pending, mut frames_written, mut centiseconds_written } => {
+                if let Some((previous, count)) = pending {
+                    write_gif_frame(&mut encoder, self.scale, previous, count, &mut frames_written, &mut centiseconds_written)?;
+                }
+                encoder.into_inner().map_err(io::Error::other)?.flush()?;
+            },
+            Video::Y4m { mut writer, .. } => writer.flush()?,
+        }
+
+        if let Some(audio) = self.audio {
+            audio.finish()?;
+        }
+        Ok(())
+    }
+
+    fn indexed_pixels(&self, chip: &Chip8) -> Vec<u8> {
+        let (width, height, scale) = if chip.get_hires() {
+            (WIDTH, HEIGHT, self.scale)
+        } else {
+            (LOWRES_WIDTH, LOWRES_HEIGHT, self.scale * 2)
+        };
+
+        let mut pixels = Vec::with_capacity(WIDTH * HEIGHT * self.scale * self.scale);
+        for row in chip.get_screen_buf().iter().take(height) {
+            for _ in 0..scale {
+                for pixel in row.iter().take(width) {
+                    pixels.extend(std::iter::repeat_n(*pixel as u8, scale));
+                }
+            }
+        }
+        pixels
+    }
+}
+
+// Browsers and most decoders play frames shown for less than this at 10 cs
+const MIN_GIF_DELAY: u64 = 2;
+
+// Centiseconds a frame gets when `frames` frames will have been written.
+// GIF delays are in centiseconds, so the rounding error is carried over to
+// keep the total length right.
+fn gif_delay(frames: u64, centiseconds_written: u64) -> u64 {
+    frames * 100 / u64::from(FRAMES_PER_SECOND) - centiseconds_written
+}
+
+// Only the last frame can be shorter than `MIN_GIF_DELAY` here, it is
+// stretched to it
+fn write_gif_frame(
+    encoder: &mut gif::Encoder<BufWriter<File>>,
+    scale: usize,
+    pixels: Vec<u8>,
+    count: u64,
+    frames_written: &mut u64,
+    centiseconds_written: &mut u64,
+) -> io::Result<()> {
+    *frames_written += count;
+    let delay = gif_delay(*frames_written, *centiseconds_written).max(MIN_GIF_DELAY);
+    *centiseconds_written += delay;
+    let delay = delay.min(u64::from(u16::MAX)) as u16;
+
+    let (width, height) = (WIDTH * scale, HEIGHT * scale);
+    let mut frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+    frame.delay = delay;
+    encoder.write_frame(&frame).map_err(io::Error::other)
+}
+
+// BT.601 studio swing
+fn rgb_to_yuv(color: u32) -> (u8, u8, u8) {
+    let r = ((color >> 16) & 0xFF) as f32;
+    let g = ((color >> 8) & 0xFF) as f32;
+    let b = (color & 0xFF) as f32;
+    let y = 16.0 + 0.257 * r + 0.504 * g + 0.098 * b;
+    let u = 128.0 - 0.148 * r - 0.291 * g + 0.439 * b;
+    let v = 128.0 + 0.439 * r - 0.368 * g - 0.071 * b;
+    (y.round() as u8, u.round() as u8, v.round() as u8)
+}
+
+// 16 bit mono PCM, the sizes in the header are filled in by `finish`
+struct Wav {
+    writer: BufWriter<File>,
+    samples_written: u32,
+}
+
+impl Wav {
+    fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { writer, samples_written: 0 })
+    }
+
+    // A frame worth of square wave while the sound timer runs, silence otherwise
+    fn write_frame(&mut self, beeping: bool) -> io::Result<()> {
+        let half_period = SAMPLE_RATE / BEEP_HZ / 2;
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = match beeping {
+                true if (self.samples_written / half_period).is_multiple_of(2) => BEEP_AMPLITUDE,
+                true => -BEEP_AMPLITUDE,
+                false => 0,
+            };
+            self.writer.write_all(&sample.to_le_bytes())?;
+            self.samples_written += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let data_size = self.samples_written * 2;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
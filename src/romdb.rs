@@ -0,0 +1,120 @@
+use std::path::Path;
+use crate::analysis::{self, Finding};
+
+// What is known about a ROM
+pub(crate) struct RomInfo {
+    pub(crate) title: &'static str,
+    pub(crate) author: Option<&'static str>,
+    pub(crate) year: Option<u16>,
+    // One of the platform names `Quirks::get_chip` understands
+    pub(crate) platform: &'static str,
+}
+
+// Well known ROMs by lowercase file name without extension. These names are
+// the ones the ROMs are usually distributed under.
+const DATABASE: [(&str, RomInfo); 13] = [
+    ("ibm logo", RomInfo { title: "IBM Logo", author: None, year: None, platform: "chip8" }),
+    ("test_opcode", RomInfo { title: "Opcode Test", author: Some("corax89"), year: None, platform: "chip8" }),
+    ("bc_test", RomInfo { title: "BC Test", author: Some("BestCoder"), year: None, platform: "chip8" }),
+    ("pong", RomInfo { title: "Pong", author: Some("Paul Vervalin"), year: Some(1990), platform: "chip8" }),
+    ("brix", RomInfo { title: "Brix", author: Some("Andreas Gustafsson"), year: Some(1990), platform: "chip8" }),
+    ("breakout", RomInfo { title: "Breakout", author: Some("Carmelo Cortez"), year: Some(1979), platform: "chip8" }),
+    ("tetris", RomInfo { title: "Tetris", author: Some("Fran Dachille"), year: Some(1991), platform: "chip8" }),
+    ("invaders", RomInfo { title: "Space Invaders", author: Some("David Winter"), year: None, platform: "chip8" }),
+    ("blinky", RomInfo { title: "Blinky", author: Some("Hans Christian Egeberg"), year: Some(1991), platform: "schip" }),
+    ("ant", RomInfo { title: "Ant", author: Some("Erin S. Catto"), year: Some(1991), platform: "schip" }),
+    ("joust", RomInfo { title: "Joust", author: Some("Erin S. Catto"), year: Some(1991), platform: "schip" }),
+    ("spacefig", RomInfo { title: "Spacefight 2091!", author: Some("Carsten Soerensen"), year: Some(1992), platform: "schip" }),
+    ("superneatboy", RomInfo { title: "Super Neat Boy", author: Some("John Earnest"), year: None, platform: "xo" }),
+];
+
+pub(crate) fn lookup(rom_name: &str) -> Option<&'static RomInfo> {
+    DATABASE
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(rom_name))
+        .map(|(_, info)| info)
+}
+
+// The platform to run a ROM on: the database entry if there is one, else
+// the file extension, else a guess from the opcodes its code uses
+pub(crate) fn detect_platform(path: &Path, rom: &[u8]) -> &'static str {
+    let name = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    if let Some(info) = lookup(&name) {
+        return info.platform;
+    }
+
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("sc8") => return "schip",
+        Some("xo8") => return "xo",
+        _ => {},
+    }
+
+    // Only code the walk reaches counts, sprite data is full of words that
+    // look like SUPER-CHIP opcodes. XO-CHIP opcodes aren't instructions to
+    // the walk, so they turn up where it stopped.
+    let analysis = analysis::analyze(rom);
+    let xo = analysis.findings
+        .iter()
+        .filter_map(|finding| match finding {
+            Finding::Invalid(at) => analysis.opcode(*at),
+            _ => None,
+        })
+        // Long I load, audio pattern, bit planes
+        .any(|word| matches!(word, 0xF000 | 0xF002) || word & 0xF0FF == 0xF001);
+    // Scrolling, hires, exit, big font, flag registers
+    let schip = analysis.opcodes().any(|word| {
+        matches!(word, 0x00C1..=0x00CF | 0x00FB..=0x00FF) || matches!(word & 0xF0FF, 0xF030 | 0xF075 | 0xF085)
+    });
+    match (xo, schip) {
+        (true, _) => "xo",
+        (false, true) => "schip",
+        (false, false) => "chip8",
+    }
+}
+
+// Octo assembly sources are listed too, but have to be assembled first
+pub(crate) fn is_source(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("8o"))
+}
+
+// Why the launcher can't start a file it lists, if it can't
+pub(crate) fn unsupported(path: &Path) -> Option<&'static str> {
+    let extension = path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("8o") => Some("Octo source, assemble it first"),
+        // Listed so they can be found, but they need more memory and
+        // opcodes than the core has
+        Some("xo8") => Some("XO-CHIP ROMs aren't supported yet"),
+        _ => None,
+    }
+}
+
+// The file extensions the launcher lists
+pub(crate) fn is_rom_file(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|extension| matches!(extension.as_str(), "ch8" | "sc8" | "xo8" | "8o"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprite_data_is_not_code() {
+        // IBM Logo has 00FF and 00FC in its sprites
+        let rom = include_bytes!("../IBM Logo.ch8");
+        assert_eq!(detect_platform(Path::new("ibm.ch8"), rom), "chip8");
+    }
+
+    #[test]
+    fn reachable_opcodes_pick_the_platform() {
+        // HIGH, then loop
+        assert_eq!(detect_platform(Path::new("a.ch8"), &[0x00, 0xFF, 0x12, 0x02]), "schip");
+        // Long I load
+        assert_eq!(detect_platform(Path::new("a.ch8"), &[0xF0, 0x00, 0x12, 0x34]), "xo");
+        // The same opcodes behind a jump are data
+        assert_eq!(detect_platform(Path::new("a.ch8"), &[0x12, 0x00, 0x00, 0xFF, 0xF0, 0x00]), "chip8");
+    }
+}
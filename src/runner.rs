@@ -0,0 +1,64 @@
+use std::time::Instant;
+use crate::cpu::{Chip8, TimingMode};
+use crate::timing::{self, CatchUp, Instruction, TimedSystem, Timing};
+use crate::vip;
+
+pub(crate) const CPU_SYSTEM: &str = "cpu";
+pub(crate) const TIMER_SYSTEM: &str = "timer";
+pub(crate) const DISPLAY_SYSTEM: &str = "display";
+
+pub(crate) const FRAMES_PER_SECOND: u32 = 60;
+
+// The systems every frontend drives the emulator with
+pub(crate) fn systems(chip: &Chip8) -> Vec<TimedSystem> {
+    let cpu_speed = match chip.timing_mode {
+        TimingMode::Fixed => 700,
+        TimingMode::CosmacVip => vip::MACHINE_CYCLES_PER_SECOND,
+    };
+
+    vec![
+        TimedSystem::new(CPU_SYSTEM, cpu_speed),
+        TimedSystem::new(TIMER_SYSTEM, 60),
+        TimedSystem::new(DISPLAY_SYSTEM, FRAMES_PER_SECOND as u64),
+    ]
+}
+
+pub(crate) fn new_timing(chip: &Chip8, catch_up: CatchUp) -> Timing {
+    let mut timing = Timing::new(Instant::now(), systems(chip));
+    timing.set_catch_up(catch_up, timing::DEFAULT_MAX_CATCH_UP);
+    timing
+}
+
+// Runs the instructions handed out by `Timing` and returns how many display
+// frames were due. While paused only the display keeps ticking.
+pub(crate) fn execute_instructions(chip: &mut Chip8, instructions: Vec<Instruction>, paused: bool) -> u64 {
+    let mut frames_due = 0;
+    for instruction in instructions {
+        match instruction.name {
+            CPU_SYSTEM | TIMER_SYSTEM if paused => {},
+            CPU_SYSTEM => match chip.timing_mode {
+                TimingMode::Fixed => {
+                    for _ in 0..instruction.cycles {
+                        chip.clock();
+                    }
+                },
+                TimingMode::CosmacVip => chip.run_machine_cycles(instruction.cycles),
+            },
+            TIMER_SYSTEM => {
+                for _ in 0..instruction.cycles {
+                    chip.update_timer();
+                }
+            },
+            DISPLAY_SYSTEM => {
+                for _ in 0..instruction.cycles {
+                    if !paused {
+                        chip.vblank();
+                    }
+                }
+                frames_due += instruction.cycles;
+            },
+            unknown => panic!("Unexpected instruction {}", unknown),
+        }
+    }
+    frames_due
+}
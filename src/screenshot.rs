@@ -0,0 +1,54 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use crate::cpu::{Chip8, HEIGHT, LOWRES_HEIGHT, LOWRES_WIDTH, WIDTH};
+use crate::palette::Palette;
+
+// The visible part of the screen as 0xRRGGBB pixels, each CHIP-8 pixel
+// repeated `scale` times in both directions. Returns the image size too.
+pub(crate) fn render(chip: &Chip8, palette: &Palette, scale: u32) -> (u32, u32, Vec<u32>) {
+    let (width, height) = if chip.get_hires() { (WIDTH, HEIGHT) } else { (LOWRES_WIDTH, LOWRES_HEIGHT) };
+    let scale = scale.max(1) as usize;
+    let screen = chip.get_screen_buf();
+
+    let mut pixels = Vec::with_capacity(width * height * scale * scale);
+    for row in screen.iter().take(height) {
+        for _ in 0..scale {
+            for pixel in row.iter().take(width) {
+                let color = palette.color(*pixel as u8);
+                pixels.extend(std::iter::repeat_n(color, scale));
+            }
+        }
+    }
+    ((width * scale) as u32, (height * scale) as u32, pixels)
+}
+
+pub(crate) fn save_png(path: &Path, chip: &Chip8, palette: &Palette, scale: u32) -> io::Result<()> {
+    let (width, height, pixels) = render(chip, palette, scale);
+    let data: Vec<u8> = pixels
+        .iter()
+        .flat_map(|color| [(color >> 16) as u8, (color >> 8) as u8, *color as u8])
+        .collect();
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+// The first `<rom name>-NNN.<extension>` in the current directory that
+// doesn't exist yet
+pub(crate) fn next_path(rom_path: &str, extension: &str) -> PathBuf {
+    let rom_name = Path::new(rom_path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "chip8".to_string());
+
+    (1..)
+        .map(|number| PathBuf::from(format!("{}-{:03}.{}", rom_name, number, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
@@ -0,0 +1,196 @@
+use std::io::{self, IsTerminal, Stdout, Write};
+use std::time::{Duration, Instant};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::style::Print;
+use crossterm::{cursor, execute, queue, terminal};
+use crate::cpu::{Chip8, HEIGHT, LOWRES_HEIGHT, LOWRES_WIDTH, WIDTH};
+use crate::headless;
+use crate::keypad::Keymap;
+use crate::pacer::FramePacer;
+use crate::runner::{self, FRAMES_PER_SECOND};
+use crate::timing::Timing;
+
+// Terminals that can't report key releases only repeat a key while it is
+// held, so a key counts as released when no repeat came in for this long.
+const KEY_HOLD: Duration = Duration::from_millis(250);
+
+// How pixels are packed into characters
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Glyphs {
+    // One column by two rows per character
+    HalfBlocks,
+    // Two columns by four rows per character
+    Braille,
+}
+
+// Runs the emulator in the terminal until Escape or Ctrl+C is pressed. When
+// output doesn't go to a terminal, like in CI logs, it runs for `frames`
+// frames instead and prints the screen as plain text.
+pub(crate) fn run(chip: &mut Chip8, timing: &mut Timing, glyphs: Glyphs, keymap: &Keymap, frames: u64) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    if !stdout.is_terminal() {
+        return run_plain(chip, glyphs, frames, &mut stdout);
+    }
+
+    terminal::enable_raw_mode()?;
+    let releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+    // Restores the terminal however the loop ends from here on
+    let _guard = TerminalGuard { releases };
+    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+    if releases {
+        execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+    }
+    run_loop(chip, timing, glyphs, keymap, releases, &mut stdout)
+}
+
+// Leaves raw mode and the alternate screen when dropped
+struct TerminalGuard {
+    releases: bool,
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        // Nothing more can be done if the terminal is gone
+        let mut stdout = io::stdout();
+        if self.releases {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// Prints the screen whenever it looks different, at most once a second of
+// emulated time, on a virtual clock so logs are the same on every run
+fn run_plain(chip: &mut Chip8, glyphs: Glyphs, frames: u64, stdout: &mut Stdout) -> io::Result<()> {
+    let mut printed: Vec<String> = Vec::new();
+    let mut frame = 0;
+    let mut result = Ok(());
+    headless::run(chip, frames, false, |chip| {
+        frame += 1;
+        if result.is_err() || (frame % FRAMES_PER_SECOND as u64 != 0 && frame != frames) {
+            return;
+        }
+        let lines = render(chip, glyphs);
+        if lines != printed {
+            result = writeln!(stdout, "Frame {}\n{}", frame, lines.join("\n"));
+            printed = lines;
+        }
+    });
+    result
+}
+
+fn run_loop(
+    chip: &mut Chip8,
+    timing: &mut Timing,
+    glyphs: Glyphs,
+    keymap: &Keymap,
+    releases: bool,
+    stdout: &mut Stdout,
+) -> io::Result<()> {
+    let mut pacer = FramePacer::new(FRAMES_PER_SECOND, false);
+    let mut held: [Option<Instant>; 16] = [None; 16];
+
+    loop {
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.code == KeyCode::Esc || ctrl_c {
+                return Ok(());
+            }
+
+            // Terminals only report symbols, so the keymap is always used
+            // with keycode names here
+            let name = match key.code {
+                KeyCode::Char(' ') => "Space".to_string(),
+                KeyCode::Char(c) => c.to_string(),
+                KeyCode::Up => "Up".to_string(),
+                KeyCode::Down => "Down".to_string(),
+                KeyCode::Left => "Left".to_string(),
+                KeyCode::Right => "Right".to_string(),
+                KeyCode::Enter => "Return".to_string(),
+                _ => continue,
+            };
+            if let Some(index) = keymap.translate(&name) {
+                let pressed = key.kind != KeyEventKind::Release;
+                chip.keypress(index, pressed);
+                held[index] = if pressed && !releases { Some(Instant::now()) } else { None };
+            }
+        }
+
+        for (index, since) in held.iter_mut().enumerate() {
+            if since.is_some_and(|since| since.elapsed() > KEY_HOLD) {
+                chip.keypress(index, false);
+                *since = None;
+            }
+        }
+
+        let frames_due = runner::execute_instructions(chip, timing.get_instructions(Instant::now()), false);
+        pacer.frame_presented(frames_due.saturating_sub(1));
+        if chip.take_screen_dirty() {
+            draw(stdout, chip, glyphs)?;
+        }
+        // Errors go on the line below the screen
+        if let Some(error) = chip.take_error() {
+            let row = render(chip, glyphs).len() as u16;
+            queue!(stdout, cursor::MoveTo(0, row), terminal::Clear(terminal::ClearType::CurrentLine), Print(error))?;
+            stdout.flush()?;
+        }
+        pacer.wait();
+    }
+}
+
+fn draw(stdout: &mut Stdout, chip: &Chip8, glyphs: Glyphs) -> io::Result<()> {
+    for (row, line) in render(chip, glyphs).into_iter().enumerate() {
+        queue!(stdout, cursor::MoveTo(0, row as u16), terminal::Clear(terminal::ClearType::CurrentLine), Print(line))?;
+    }
+    stdout.flush()
+}
+
+// The screen as lines of characters
+fn render(chip: &Chip8, glyphs: Glyphs) -> Vec<String> {
+    let screen = chip.get_screen_buf();
+    let (width, height) = if chip.get_hires() { (WIDTH, HEIGHT) } else { (LOWRES_WIDTH, LOWRES_HEIGHT) };
+    let lit = |x: usize, y: usize| x < width && y < height && screen[y][x];
+
+    let (cell_width, cell_height) = match glyphs {
+        Glyphs::HalfBlocks => (1, 2),
+        Glyphs::Braille => (2, 4),
+    };
+
+    (0..height.div_ceil(cell_height))
+        .map(|row| {
+            (0..width.div_ceil(cell_width))
+                .map(|column| {
+                    let x = column * cell_width;
+                    let y = row * cell_height;
+                    match glyphs {
+                        Glyphs::HalfBlocks => match (lit(x, y), lit(x, y + 1)) {
+                            (false, false) => ' ',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (true, true) => '█',
+                        },
+                        Glyphs::Braille => {
+                            // Unicode braille dot numbering, column by column
+                            const DOTS: [(usize, usize, u32); 8] = [
+                                (0, 0, 0x01), (0, 1, 0x02), (0, 2, 0x04), (1, 0, 0x08),
+                                (1, 1, 0x10), (1, 2, 0x20), (0, 3, 0x40), (1, 3, 0x80),
+                            ];
+                            let bits = DOTS
+                                .iter()
+                                .filter(|(dx, dy, _)| lit(x + dx, y + dy))
+                                .fold(0, |bits, (_, _, bit)| bits | bit);
+                            char::from_u32(0x2800 + bits).unwrap_or(' ')
+                        },
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,56 @@
+use sdl3::pixels::Color;
+use sdl3::rect::Rect;
+use sdl3::render::Canvas;
+use sdl3::video::Window;
+use crate::font;
+
+// Size of a font pixel in window pixels
+pub(crate) const OVERLAY_SCALE: u32 = 3;
+
+// Height of a line of text including its backing box
+pub(crate) const LINE_HEIGHT: u32 = (font::GLYPH_HEIGHT + 2) * OVERLAY_SCALE;
+
+// Draws `text` with the overlay font on a dark backing box so it stays
+// readable on top of lit pixels
+pub(crate) fn draw_text(canvas: &mut Canvas<Window>, x: u32, y: u32, text: &str) {
+    draw_label(canvas, x, y, text, Color::RGBA(0, 0, 0, 255), Color::RGBA(255, 255, 0, 255));
+}
+
+pub(crate) fn draw_label(canvas: &mut Canvas<Window>, x: u32, y: u32, text: &str, background: Color, foreground: Color) {
+    draw_scaled(canvas, x, y, OVERLAY_SCALE, text, background, foreground);
+}
+
+// Like `draw_label` with font pixels of `scale` window pixels. A line is
+// `font::GLYPH_HEIGHT + 2` font pixels high.
+pub(crate) fn draw_scaled(
+    canvas: &mut Canvas<Window>,
+    x: u32,
+    y: u32,
+    scale: u32,
+    text: &str,
+    background: Color,
+    foreground: Color,
+) {
+    let width = (font::text_width(text) + 2) * scale;
+    let height = (font::GLYPH_HEIGHT + 2) * scale;
+    canvas.set_draw_color(background);
+    canvas.fill_rect(Rect::new(x as i32, y as i32, width, height)).unwrap();
+
+    canvas.set_draw_color(foreground);
+    for (index, c) in text.chars().enumerate() {
+        let glyph_x = x + (1 + index as u32 * (font::GLYPH_WIDTH + 1)) * scale;
+        for (row, bits) in font::glyph(c).iter().enumerate() {
+            for col in 0..font::GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    let rect = Rect::new(
+                        (glyph_x + col * scale) as i32,
+                        (y + (1 + row as u32) * scale) as i32,
+                        scale,
+                        scale,
+                    );
+                    canvas.fill_rect(rect).unwrap();
+                }
+            }
+        }
+    }
+}
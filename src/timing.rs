@@ -1,4 +1,4 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
 macro_rules! debug {
@@ -7,6 +7,10 @@ macro_rules! debug {
     }
 }
 
+// How far behind the wall clock we are allowed to fall before the scheduler
+// stops trying to catch up (e.g. after the process was suspended).
+pub const DEFAULT_MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
 pub struct TimedSystem {
     name: &'static str,
     cycle_duration_nanos: u64,
@@ -23,24 +27,23 @@ impl TimedSystem {
     }
 
     fn next_cycle_nanos(&self) -> u64 {
-        return self.cycle_duration_nanos * (self.elapsed_cycles + 1);
+        self.cycle_duration_nanos * (self.elapsed_cycles + 1)
     }
 
     // Number of cycles that can be executed until we are > the target_nanos
     fn num_cycles_until(&self, target_nanos: u64) -> u64 {
         let next_nanos = self.next_cycle_nanos();
-        if target_nanos == next_nanos {
+        if target_nanos <= next_nanos {
             return 1;
         }
 
-        return TimedSystem::divide_round_up(
-            target_nanos - next_nanos,
-            self.cycle_duration_nanos,
-        );
+        (target_nanos - next_nanos).div_ceil(self.cycle_duration_nanos)
     }
 
-    fn divide_round_up(a: u64, b: u64) -> u64 {
-        return (a + b - 1) / b;
+    // Move the system to the first cycle at or after `nanos`, as if it had
+    // been running since the start of the clock.
+    fn align_to(&mut self, nanos: u64) {
+        self.elapsed_cycles = nanos / self.cycle_duration_nanos;
     }
 }
 
@@ -49,9 +52,30 @@ pub struct Instruction {
     pub cycles: u64,
 }
 
+// What to do when the wall clock is further ahead than `max_catch_up`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CatchUp {
+    // Drop the missing cycles; emulated time jumps to stay in sync with the wall clock
+    Skip,
+    // Pretend the missing time never happened; emulated time falls behind the wall clock
+    TimeWarp,
+}
+
+enum Clock {
+    Real(Instant),
+    // Nanoseconds since the start, only moved by `Timing::advance`
+    Virtual(u64),
+}
+
 pub struct Timing {
-    start_time: Instant,
+    clock: Clock,
     systems: Vec<TimedSystem>,
+    // Nanoseconds of wall clock time thrown away by `CatchUp::TimeWarp`
+    warped_nanos: u64,
+    // Point in emulated time up to which every system has been scheduled
+    scheduled_nanos: u64,
+    catch_up: CatchUp,
+    max_catch_up_nanos: u64,
 }
 
 impl Timing {
@@ -60,54 +84,197 @@ impl Timing {
         current_time: Instant,
         systems: Vec<TimedSystem>,
     ) -> Self {
+        Self::with_clock(Clock::Real(current_time), systems)
+    }
+
+    // A clock that only moves when `advance` is called, so tests and headless
+    // runs are deterministic regardless of how fast the host is.
+    pub fn new_virtual(systems: Vec<TimedSystem>) -> Self {
+        Self::with_clock(Clock::Virtual(0), systems)
+    }
+
+    fn with_clock(clock: Clock, systems: Vec<TimedSystem>) -> Self {
         Self {
-            start_time: current_time,
+            clock,
             systems,
+            warped_nanos: 0,
+            scheduled_nanos: 0,
+            catch_up: CatchUp::TimeWarp,
+            max_catch_up_nanos: DEFAULT_MAX_CATCH_UP.as_nanos() as u64,
         }
     }
 
+    pub fn set_catch_up(&mut self, catch_up: CatchUp, max_catch_up: Duration) {
+        self.catch_up = catch_up;
+        self.max_catch_up_nanos = max_catch_up.as_nanos() as u64;
+    }
+
     pub fn get_instructions(&mut self, current_time: Instant) -> Vec<Instruction> {
-        let required_nanos = (current_time - self.start_time).as_nanos();
+        let now_nanos = match self.clock {
+            Clock::Real(start_time) => current_time.saturating_duration_since(start_time).as_nanos() as u64,
+            Clock::Virtual(now_nanos) => now_nanos,
+        };
+        self.schedule(now_nanos)
+    }
 
-        let mut results: Vec<Instruction> = Vec::new();
-        let mut watchdog = 0;
-        loop {
-            if watchdog > 1_000 {
-                panic!("Infinite loop");
-            }
-            watchdog += 1;
+    // Moves a virtual clock forward and returns everything that became due.
+    pub fn advance(&mut self, duration: Duration) -> Vec<Instruction> {
+        let now_nanos = match &mut self.clock {
+            Clock::Virtual(now_nanos) => {
+                *now_nanos += duration.as_nanos() as u64;
+                *now_nanos
+            },
+            Clock::Real(_) => panic!("advance() requires a virtual clock"),
+        };
+        self.schedule(now_nanos)
+    }
 
-            // Sort systems by the soonest next cycle
-            self.systems
-                .sort_by(|a, b| a.next_cycle_nanos().cmp(&b.next_cycle_nanos()));
+    fn schedule(&mut self, now_nanos: u64) -> Vec<Instruction> {
+        let mut required_nanos = now_nanos.saturating_sub(self.warped_nanos);
+        self.limit_catch_up(&mut required_nanos);
 
-            for _system in &self.systems {
-                debug!("Sorted {} at cycle {}", _system.name, _system.next_cycle_nanos());
-            }
+        let mut results: Vec<Instruction> = Vec::new();
+        // Find the soonest and next soonest systems, no matter how many there are
+        while let Some(soonest) = (0..self.systems.len())
+            .min_by_key(|&index| self.systems[index].next_cycle_nanos()) {
+            let next_soonest_nanos = self.systems.iter()
+                .enumerate()
+                .filter(|(index, _)| *index != soonest)
+                .map(|(_, system)| system.next_cycle_nanos())
+                .min()
+                .unwrap_or(u64::MAX);
 
-            let soonest_nanos = self.systems[0].next_cycle_nanos();
-            let next_soonest_nanos = self.systems[1].next_cycle_nanos();
+            let system = &mut self.systems[soonest];
+            debug!("Soonest {} at cycle {}", system.name, system.next_cycle_nanos());
 
-            if u128::from(soonest_nanos) >= required_nanos {
+            if system.next_cycle_nanos() >= required_nanos {
                 break;
             }
 
             // We put as many cycles as we can from the soonest system, until
-            // it is no longer the soonest system
-            let num_cycles = self.systems[0].num_cycles_until(next_soonest_nanos);
+            // it is no longer the soonest system or we run out of time
+            let num_cycles = system.num_cycles_until(next_soonest_nanos.min(required_nanos));
 
             // Add it to our results
-            debug!("Adding instruction {} for {} cycles", self.systems[0].name, num_cycles);
+            debug!("Adding instruction {} for {} cycles", system.name, num_cycles);
             results.push(Instruction {
-                name: self.systems[0].name,
+                name: system.name,
                 cycles: num_cycles,
             });
 
             // Update our systems to take into account the cycles that will be executed
-            self.systems[0].elapsed_cycles += num_cycles;
+            system.elapsed_cycles += num_cycles;
         }
 
+        self.scheduled_nanos = self.scheduled_nanos.max(required_nanos);
         debug!("--- Emitted {} instructions", results.len());
-        return results;
+        results
+    }
+
+    fn limit_catch_up(&mut self, required_nanos: &mut u64) {
+        let behind_nanos = required_nanos.saturating_sub(self.scheduled_nanos);
+        if behind_nanos <= self.max_catch_up_nanos {
+            return;
+        }
+
+        let excess_nanos = behind_nanos - self.max_catch_up_nanos;
+        debug!("{}ns behind, dropping {}ns", behind_nanos, excess_nanos);
+        match self.catch_up {
+            CatchUp::Skip => {
+                let resume_nanos = *required_nanos - self.max_catch_up_nanos;
+                for system in &mut self.systems {
+                    system.align_to(resume_nanos);
+                }
+                self.scheduled_nanos = resume_nanos;
+            },
+            CatchUp::TimeWarp => {
+                self.warped_nanos += excess_nanos;
+                *required_nanos -= excess_nanos;
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cycles handed out to the named system
+    fn cycles(instructions: &[Instruction], name: &str) -> u64 {
+        instructions.iter().filter(|instruction| instruction.name == name).map(|instruction| instruction.cycles).sum()
+    }
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    // Advances in 10ms frames like a frontend would, staying well within
+    // the catch up limit
+    fn run(timing: &mut Timing, duration: Duration) -> Vec<Instruction> {
+        let frame = millis(10);
+        let mut instructions = Vec::new();
+        for _ in 0..duration.as_millis() / frame.as_millis() {
+            instructions.extend(timing.advance(frame));
+        }
+        instructions
+    }
+
+    #[test]
+    fn virtual_clock_drives_one_system() {
+        let mut timing = Timing::new_virtual(vec![TimedSystem::new("cpu", 1000)]);
+        assert!(timing.get_instructions(Instant::now()).is_empty());
+
+        // The cycle ending exactly at one second isn't due yet
+        assert_eq!(cycles(&run(&mut timing, Duration::from_secs(1)), "cpu"), 999);
+        assert_eq!(cycles(&timing.advance(millis(1)), "cpu"), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn virtual_clock_drives_several_systems() {
+        let mut timing = Timing::new_virtual(vec![
+            TimedSystem::new("cpu", 600),
+            TimedSystem::new("timer", 60),
+            TimedSystem::new("display", 30),
+        ]);
+        let mut instructions = run(&mut timing, Duration::from_secs(1));
+        instructions.extend(timing.advance(millis(1)));
+        assert_eq!(cycles(&instructions, "cpu"), 600);
+        assert_eq!(cycles(&instructions, "timer"), 60);
+        assert_eq!(cycles(&instructions, "display"), 30);
+
+        // Systems are interleaved in time order, not run one after the other
+        let first_timer = instructions.iter().position(|instruction| instruction.name == "timer").unwrap();
+        assert_eq!(cycles(&instructions[..first_timer], "cpu"), 10);
+    }
+
+    #[test]
+    fn time_warp_resumes_where_emulation_stopped() {
+        let mut timing = Timing::new_virtual(vec![TimedSystem::new("cpu", 1000), TimedSystem::new("second", 1)]);
+        timing.set_catch_up(CatchUp::TimeWarp, millis(250));
+        run(&mut timing, millis(500));
+
+        // Only the allowed catch up is run after a long stall
+        let instructions = timing.advance(Duration::from_secs(10));
+        assert_eq!(cycles(&instructions, "cpu"), 250);
+        assert_eq!(cycles(&instructions, "second"), 0);
+
+        // Emulated time is at 0.75s, so the first second ends 0.25s later
+        assert_eq!(cycles(&run(&mut timing, millis(300)), "second"), 1);
+    }
+
+    #[test]
+    fn skip_jumps_to_the_wall_clock() {
+        let mut timing = Timing::new_virtual(vec![TimedSystem::new("cpu", 1000), TimedSystem::new("second", 1)]);
+        timing.set_catch_up(CatchUp::Skip, millis(250));
+        run(&mut timing, millis(500));
+
+        // The cycle ending at the point resumed from counts as skipped
+        let instructions = timing.advance(Duration::from_secs(10));
+        assert_eq!(cycles(&instructions, "cpu"), 249);
+        assert_eq!(cycles(&instructions, "second"), 0);
+
+        // Emulated time is back in step at 10.5s, the skipped seconds are gone
+        assert_eq!(cycles(&run(&mut timing, millis(300)), "second"), 0);
+        assert_eq!(cycles(&run(&mut timing, millis(300)), "second"), 1);
+    }
+}
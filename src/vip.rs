@@ -0,0 +1,116 @@
+// Instruction costs of the original CHIP-8 interpreter running on the
+// RCA COSMAC VIP, in 1802 machine cycles (8 clock periods each).
+//
+// The figures follow the published analyses of the VIP interpreter listing,
+// rounded to whole cycles, and include the interpreter's fetch/decode loop.
+
+// 1.7609 MHz crystal, 8 clock periods per machine cycle
+pub(crate) const MACHINE_CYCLES_PER_SECOND: u64 = 1_760_900 / 8;
+
+// Cycles the CDP1861 display interrupt takes away from the interpreter every
+// frame: 128 scanlines of 8 DMA bytes plus the interrupt service routine,
+// which also decrements the delay and sound timers.
+pub(crate) const VBLANK_INTERRUPT_CYCLES: i64 = 128 * 8 + 46;
+
+const FETCH_CYCLES: u32 = 40;
+
+// Cost of `operation`, where `vx` is the value of VX before the instruction
+// ran and `skipped` tells whether a conditional skip was taken.
+pub(crate) fn instruction_cycles(operation: u16, vx: u8, skipped: bool) -> u32 {
+    let op1 = (operation & 0xF000) >> 12;
+    let op2 = (operation & 0x0F00) >> 8;
+    let op3 = (operation & 0x00F0) >> 4;
+    let op4 = operation & 0x000F;
+    let skip_cycles = if skipped { 4 } else { 0 };
+
+    let cycles = match (op1, op2, op3, op4) {
+        // Clearing the 256 byte display buffer a byte at a time
+        (0, 0, 0xE, 0) => 24 + 3078,
+        (0, 0, 0xE, 0xE) => 10,
+        // Machine language subroutine, we can't know how long it would take
+        (0, _, _, _) => 0,
+        (1, _, _, _) => 12,
+        (2, _, _, _) => 26,
+        (3, _, _, _) | (4, _, _, _) => 10 + skip_cycles,
+        (5, _, _, _) | (9, _, _, _) => 14 + skip_cycles,
+        (6, _, _, _) => 6,
+        (7, _, _, _) => 10,
+        (8, _, _, 0) => 12,
+        (8, _, _, _) => 44,
+        (0xA, _, _, _) => 12,
+        (0xB, _, _, _) => 22,
+        (0xC, _, _, _) => 36,
+        (0xD, _, _, _) => sprite_cycles(vx, op4),
+        (0xE, _, _, _) => 14 + skip_cycles,
+        (0xF, _, 0, 7) => 10,
+        (0xF, _, 0, 0xA) => 19,
+        (0xF, _, 1, 5) | (0xF, _, 1, 8) => 10,
+        (0xF, _, 1, 0xE) => 16,
+        (0xF, _, 2, 9) => 16,
+        // BCD is done by repeated subtraction, so it depends on the digits
+        (0xF, _, 3, 3) => {
+            let digits = u32::from(vx / 100) + u32::from(vx / 10 % 10) + u32::from(vx % 10);
+            84 + 16 * digits
+        },
+        (0xF, _, 5, 5) | (0xF, _, 6, 5) => 14 + 14 * (u32::from(op2) + 1),
+        _ => 0,
+    };
+
+    FETCH_CYCLES + cycles
+}
+
+// DXYN shifts every sprite row into place one bit at a time, so unaligned
+// sprites are more expensive than ones drawn on a byte boundary.
+fn sprite_cycles(x_coord: u8, rows: u16) -> u32 {
+    let shift = u32::from(x_coord % 8);
+    let row_cycles = if shift == 0 { 34 } else { 46 + 8 * shift };
+    26 + row_cycles * u32::from(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Totals include the 40 cycle fetch
+    #[test]
+    fn simple_instructions() {
+        assert_eq!(instruction_cycles(0x00E0, 0, false), 3142);
+        assert_eq!(instruction_cycles(0x00EE, 0, false), 50);
+        assert_eq!(instruction_cycles(0x1234, 0, false), 52);
+        assert_eq!(instruction_cycles(0x2234, 0, false), 66);
+        assert_eq!(instruction_cycles(0x6A12, 0, false), 46);
+        assert_eq!(instruction_cycles(0x8120, 0, false), 52);
+        assert_eq!(instruction_cycles(0x8124, 0, false), 84);
+        assert_eq!(instruction_cycles(0xA123, 0, false), 52);
+        assert_eq!(instruction_cycles(0xF00A, 0, false), 59);
+    }
+
+    #[test]
+    fn taken_skips_cost_more() {
+        assert_eq!(instruction_cycles(0x3012, 0, false), 50);
+        assert_eq!(instruction_cycles(0x3012, 0, true), 54);
+        assert_eq!(instruction_cycles(0x5120, 0, false), 54);
+        assert_eq!(instruction_cycles(0x9120, 0, true), 58);
+        assert_eq!(instruction_cycles(0xE19E, 0, true), 58);
+        assert_eq!(instruction_cycles(0xE1A1, 0, false), 54);
+    }
+
+    #[test]
+    fn sprites_depend_on_height_and_alignment() {
+        assert_eq!(instruction_cycles(0xD120, 8, false), 66);
+        assert_eq!(instruction_cycles(0xD121, 8, false), 100);
+        assert_eq!(instruction_cycles(0xD125, 8, false), 236);
+        // Three bits off the byte boundary
+        assert_eq!(instruction_cycles(0xD125, 3, false), 416);
+        assert_eq!(instruction_cycles(0xD12F, 7, false), 1596);
+    }
+
+    #[test]
+    fn memory_instructions_depend_on_their_operands() {
+        assert_eq!(instruction_cycles(0xF033, 0, false), 124);
+        assert_eq!(instruction_cycles(0xF033, 255, false), 316);
+        assert_eq!(instruction_cycles(0xF055, 0, false), 68);
+        assert_eq!(instruction_cycles(0xF355, 0, false), 110);
+        assert_eq!(instruction_cycles(0xFF65, 0, false), 278);
+    }
+}
@@ -0,0 +1,53 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// How often the ROM file is looked at
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Notices when the ROM file is written, so a game can be rebuilt and
+// restarted without touching the emulator. Polls the modification time
+// instead of needing platform file notification APIs.
+pub(crate) struct RomWatcher {
+    path: PathBuf,
+    // Modification time and size of the version last reported
+    current: Option<(SystemTime, u64)>,
+    // A newer version seen on the last poll, reported once it stops changing
+    pending: Option<(SystemTime, u64)>,
+    last_poll: Instant,
+}
+
+impl RomWatcher {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        let current = file_state(&path);
+        Self { path, current, pending: None, last_poll: Instant::now() }
+    }
+
+    // Whether the file changed since the last call that returned true. A
+    // missing file, like in the middle of a save, isn't a change, and
+    // neither is one still being written: it has to look the same on two
+    // polls in a row.
+    pub(crate) fn changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let state = file_state(&self.path);
+        if state.is_none() || state == self.current {
+            self.pending = None;
+            return false;
+        }
+        if state != self.pending {
+            self.pending = state;
+            return false;
+        }
+        self.current = self.pending.take();
+        true
+    }
+}
+
+fn file_state(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
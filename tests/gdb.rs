@@ -0,0 +1,193 @@
+// Drives the GDB server of the emulator binary over a loopback connection
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::Duration;
+
+// V0 = 5, then V0 += 1 in a loop: 0x200 LD V0, 5; 0x202 ADD V0, 1; 0x204 JP 0x202
+const ROM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+
+struct Client {
+    stream: TcpStream,
+    server: Child,
+    // Kept open so the server can keep printing
+    _output: BufReader<ChildStdout>,
+}
+
+impl Client {
+    fn start(name: &str) -> Self {
+        let rom_path = env::temp_dir().join(format!("chip8-gdb-{}-{}.ch8", name, std::process::id()));
+        fs::write(&rom_path, ROM).unwrap();
+        let mut server = Command::new(env!("CARGO_BIN_EXE_chip8"))
+            .arg(&rom_path)
+            .args(["chip8", "--frontend", "headless", "--gdb", "0"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut output = BufReader::new(server.stdout.take().unwrap());
+        let mut line = String::new();
+        output.read_line(&mut line).unwrap();
+        let address = line.trim().strip_prefix("GDB server listening on ").expect(&line).to_string();
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        Self { stream, server, _output: output }
+    }
+
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+    }
+
+    fn receive(&mut self) -> String {
+        let mut byte = [0];
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            self.stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum).unwrap();
+        let expected = data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        assert_eq!(std::str::from_utf8(&checksum).unwrap(), format!("{:02x}", expected));
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, data: &str) -> String {
+        self.send(data);
+        self.receive()
+    }
+
+    // PC from the `g` packet, after V0-VF, I and SP
+    fn pc(&mut self) -> String {
+        self.request("g")[40..44].to_string()
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.server.kill();
+        let _ = self.server.wait();
+    }
+}
+
+#[test]
+fn reads_registers_and_memory() {
+    let mut client = Client::start("registers");
+    assert!(client.request("qSupported:swbreak+").contains("qXfer:features:read+"));
+    assert_eq!(client.request("?"), "S05");
+
+    let registers = client.request("g");
+    assert_eq!(registers.len(), 48);
+    assert_eq!(client.pc(), "0002");
+    assert_eq!(client.request("m200,6"), "600570011202");
+    assert_eq!(client.request("p12"), "0002");
+
+    let xml = client.request("qXfer:features:read:target.xml:0,fff");
+    assert!(xml.starts_with('l'));
+    assert!(xml.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+}
+
+#[test]
+fn writes_registers_and_memory() {
+    let mut client = Client::start("write");
+    assert_eq!(client.request("M300,2:abcd"), "OK");
+    assert_eq!(client.request("m300,2"), "abcd");
+    assert_eq!(client.request("m fff0,20").chars().next(), Some('E'));
+
+    assert_eq!(client.request("P3=7f"), "OK");
+    assert_eq!(client.request("p3"), "7f");
+    assert_eq!(client.request("P12=0403"), "OK");
+    assert_eq!(client.pc(), "0403");
+}
+
+#[test]
+fn rejects_bad_requests() {
+    let mut client = Client::start("reject");
+    // PCs that would fetch past the end of memory
+    assert_eq!(client.request("P12=ff0f"), "E01");
+    assert_eq!(client.request("sfff"), "E01");
+    assert_eq!(client.request("cfff"), "E01");
+    assert_eq!(client.pc(), "0002");
+    assert_eq!(client.request("s"), "S05");
+
+    // Odd addresses run like any other
+    assert_eq!(client.request("M203,2:6107"), "OK");
+    assert_eq!(client.request("P12=0302"), "OK");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.pc(), "0502");
+    assert_eq!(client.request("p1"), "07");
+
+    let xml = client.request("qXfer:features:read:target.xml:0,ffffffffffffffff");
+    assert!(xml.starts_with('l'));
+
+    // Stores through I past the end of memory stop like an illegal
+    // instruction and leave the server running
+    assert_eq!(client.request("P10=ff0f"), "OK");
+    assert_eq!(client.request("M200,2:f255"), "OK");
+    assert_eq!(client.request("P12=0002"), "OK");
+    assert_eq!(client.request("s"), "S04");
+    assert_eq!(client.pc(), "0202");
+}
+
+#[test]
+fn steps_and_stops_at_breakpoints() {
+    let mut client = Client::start("breakpoints");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.pc(), "0202");
+    assert_eq!(client.request("p0"), "05");
+
+    assert_eq!(client.request("Z0,204,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.pc(), "0402");
+    assert_eq!(client.request("p0"), "06");
+
+    // Continuing from a breakpoint runs past it until it comes around again
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.pc(), "0402");
+    assert_eq!(client.request("p0"), "07");
+
+    assert_eq!(client.request("z0,204,2"), "OK");
+    client.send("c");
+    std::thread::sleep(Duration::from_millis(100));
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.receive(), "S02");
+}
+
+#[test]
+fn runs_backwards() {
+    let mut client = Client::start("reverse");
+    assert!(client.request("qSupported").contains("ReverseContinue+"));
+    assert_eq!(client.request("Z0,204,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p0"), "07");
+
+    assert_eq!(client.request("bs"), "S05");
+    assert_eq!(client.pc(), "0202");
+    assert_eq!(client.request("p0"), "06");
+
+    assert_eq!(client.request("bc"), "S05");
+    assert_eq!(client.pc(), "0402");
+    assert_eq!(client.request("p0"), "06");
+
+    assert_eq!(client.request("bc"), "T05replaylog:begin;");
+    assert_eq!(client.pc(), "0002");
+    assert_eq!(client.request("p0"), "00");
+
+    // Forward again from the start of the history
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p0"), "06");
+}